@@ -0,0 +1,83 @@
+use std::fmt::Display;
+
+/// An error in a formula, at a byte position when it is known.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormulaError {
+    pub message: String,
+    pub position: Option<usize>,
+    /// The formula source, when the error was found compiling it.
+    pub formula: Option<String>,
+}
+
+impl FormulaError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            position: None,
+            formula: None,
+        }
+    }
+
+    pub fn at(position: usize, message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            position: Some(position),
+            formula: None,
+        }
+    }
+
+    pub fn in_formula(mut self, formula: &str) -> Self {
+        self.formula = Some(formula.to_string());
+        self
+    }
+
+    /// The formula with the error position pointed out below it, then the message.
+    pub fn highlight(&self) -> String {
+        match (&self.formula, self.position) {
+            // Only single line formulas can be pointed in
+            (Some(formula), Some(position)) if !formula.contains('\n') => {
+                let column = formula[..position.min(formula.len())].chars().count();
+                format!("{formula}\n{:column$}^ {}", "", self.message)
+            }
+            _ => self.to_string(),
+        }
+    }
+}
+
+impl Display for FormulaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.position {
+            Some(position) => write!(f, "{} at position {position}", self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for FormulaError {}
+
+impl From<rlua::Error> for FormulaError {
+    /// Keep the Lua message, without its chunk name.
+    fn from(error: rlua::Error) -> Self {
+        let message = lua_message(&error);
+        let message = match message.strip_prefix("[string \"?\"]:") {
+            Some(rest) => format!("line {rest}"),
+            None => message,
+        };
+        // Runtime errors end with a traceback
+        let message = match message.split_once("\nstack traceback:") {
+            Some((message, _)) => message.to_string(),
+            None => message,
+        };
+
+        Self::new(message)
+    }
+}
+
+fn lua_message(error: &rlua::Error) -> String {
+    match error {
+        rlua::Error::SyntaxError { message, .. } => message.clone(),
+        rlua::Error::RuntimeError(message) => message.clone(),
+        rlua::Error::CallbackError { cause, .. } => lua_message(cause),
+        error => error.to_string(),
+    }
+}
@@ -0,0 +1,284 @@
+use std::fmt::Display;
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::{BinaryOp, Expr, ExprFormula};
+
+/// Operators of generated formulas, with their weights.
+const OPERATORS: [(BinaryOp, u32); 9] = [
+    (BinaryOp::Mul, 4),
+    (BinaryOp::Add, 2),
+    (BinaryOp::Sub, 2),
+    (BinaryOp::Shr, 5),
+    (BinaryOp::BitAnd, 4),
+    (BinaryOp::BitOr, 4),
+    (BinaryOp::BitXor, 3),
+    (BinaryOp::Rem, 2),
+    (BinaryOp::Div, 1),
+];
+const CONSTANTS: [i32; 14] = [3, 5, 7, 8, 10, 12, 15, 16, 31, 42, 63, 64, 127, 255];
+const MAX_DEPTH: usize = 5;
+
+/// Values of `t` where candidates are listened to.
+const WINDOWS: [u32; 4] = [0, 1 << 14, 1 << 16, 1 << 18];
+const WINDOW_LENGTH: u32 = 4096;
+/// Lags looked at for repetitions, up to 31 Hz at 8 kHz.
+const MAX_LAG: usize = 256;
+
+/// Why a candidate formula was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    /// Silence, or a constant value.
+    Silent,
+    /// No repetition, like white noise.
+    Noise,
+}
+
+impl Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Silent => "silent",
+                Self::Noise => "noise",
+            }
+        )
+    }
+}
+
+/// Generates bytebeat formulas, from scratch or from other formulas.
+pub struct Explorer {
+    rng: StdRng,
+}
+
+impl Explorer {
+    pub fn new(seed: Option<u64>) -> Self {
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Self { rng }
+    }
+
+    /// A random formula using `t`.
+    pub fn generate(&mut self) -> Expr {
+        loop {
+            let expr = self.random_expr(MAX_DEPTH);
+            if uses_t(&expr) {
+                return expr;
+            }
+        }
+    }
+
+    fn random_expr(&mut self, depth: usize) -> Expr {
+        if depth == 0 || self.rng.gen_bool(0.25) {
+            return if self.rng.gen_bool(0.6) {
+                Expr::Var(0)
+            } else {
+                Expr::Number(*CONSTANTS.choose(&mut self.rng).unwrap())
+            };
+        }
+
+        let (op, _) = *OPERATORS
+            .choose_weighted(&mut self.rng, |(_, weight)| *weight)
+            .unwrap();
+        let left = self.random_expr(depth - 1);
+        // Shifts mostly by small amounts, for slow rhythms and melodies
+        let right = if op == BinaryOp::Shr && self.rng.gen_bool(0.8) {
+            Expr::Number(self.rng.gen_range(1..=14))
+        } else {
+            self.random_expr(depth - 1)
+        };
+        Expr::Binary(op, Box::new(left), Box::new(right))
+    }
+
+    /// A copy of `expr` with one random change.
+    pub fn mutate(&mut self, expr: &Expr) -> Expr {
+        let target = self.rng.gen_range(0..count_nodes(expr));
+        let mutated = replace_node(expr, target, &mut |node| match node {
+            Expr::Number(value) if self.rng.gen_bool(0.5) => {
+                let value = match self.rng.gen_range(0..3) {
+                    0 => value.wrapping_add(1),
+                    1 => value.wrapping_sub(1).max(1),
+                    _ => *CONSTANTS.choose(&mut self.rng).unwrap(),
+                };
+                Expr::Number(value)
+            }
+            Expr::Binary(op, left, right) if self.rng.gen_bool(0.6) => {
+                if self.rng.gen_bool(0.5) {
+                    let (op, _) = *OPERATORS.choose(&mut self.rng).unwrap();
+                    Expr::Binary(op, left.clone(), right.clone())
+                } else {
+                    Expr::Binary(*op, right.clone(), left.clone())
+                }
+            }
+            _ => self.random_expr(2),
+        });
+
+        if uses_t(&mutated) {
+            mutated
+        } else {
+            expr.clone()
+        }
+    }
+
+    /// A copy of `a` with a random part replaced by a random part of `b`.
+    pub fn crossover(&mut self, a: &Expr, b: &Expr) -> Expr {
+        let donor = node_at(b, self.rng.gen_range(0..count_nodes(b))).clone();
+        let target = self.rng.gen_range(0..count_nodes(a));
+        let child = replace_node(a, target, &mut |_| donor.clone());
+
+        if uses_t(&child) {
+            child
+        } else {
+            a.clone()
+        }
+    }
+
+    /// A new formula passing `check_candidate`, made from `parents` if any, or `None` if no candidate was
+    /// found within `attempts`.
+    pub fn candidate(&mut self, parents: &[Expr], attempts: usize) -> Option<Expr> {
+        for _ in 0..attempts {
+            let candidate = match parents {
+                [] => self.generate(),
+                [parent] => self.mutate(parent),
+                parents => {
+                    let a = parents.choose(&mut self.rng).unwrap();
+                    let b = parents.choose(&mut self.rng).unwrap();
+                    let child = self.crossover(a, b);
+                    self.mutate(&child)
+                }
+            };
+
+            if parents.contains(&candidate) {
+                continue;
+            }
+            if check_candidate(&candidate).is_ok() {
+                return Some(candidate);
+            }
+        }
+
+        None
+    }
+}
+
+fn uses_t(expr: &Expr) -> bool {
+    match expr {
+        Expr::Number(_) => false,
+        Expr::Var(index) => *index == 0,
+        Expr::Unary(_, operand) => uses_t(operand),
+        Expr::Binary(_, left, right) => uses_t(left) || uses_t(right),
+        Expr::Ternary(condition, then, otherwise) => {
+            uses_t(condition) || uses_t(then) || uses_t(otherwise)
+        }
+    }
+}
+
+fn children(expr: &Expr) -> Vec<&Expr> {
+    match expr {
+        Expr::Number(_) | Expr::Var(_) => vec![],
+        Expr::Unary(_, operand) => vec![operand],
+        Expr::Binary(_, left, right) => vec![left, right],
+        Expr::Ternary(condition, then, otherwise) => vec![condition, then, otherwise],
+    }
+}
+
+fn count_nodes(expr: &Expr) -> usize {
+    1 + children(expr).into_iter().map(count_nodes).sum::<usize>()
+}
+
+/// Node at `index`, in depth-first order.
+fn node_at(expr: &Expr, index: usize) -> &Expr {
+    let mut index = index;
+    fn visit<'a>(expr: &'a Expr, index: &mut usize) -> Option<&'a Expr> {
+        if *index == 0 {
+            return Some(expr);
+        }
+        *index -= 1;
+        children(expr)
+            .into_iter()
+            .find_map(|child| visit(child, index))
+    }
+    visit(expr, &mut index).unwrap_or(expr)
+}
+
+/// A copy of `expr` with its node at `index`, in depth-first order, replaced.
+fn replace_node(expr: &Expr, index: usize, replace: &mut dyn FnMut(&Expr) -> Expr) -> Expr {
+    fn visit(expr: &Expr, index: &mut usize, replace: &mut dyn FnMut(&Expr) -> Expr) -> Expr {
+        if *index == 0 {
+            *index = usize::MAX;
+            return replace(expr);
+        }
+        if *index != usize::MAX {
+            *index -= 1;
+        }
+
+        let mut child = |expr: &Expr| Box::new(visit(expr, index, replace));
+        match expr {
+            Expr::Number(_) | Expr::Var(_) => expr.clone(),
+            Expr::Unary(op, operand) => Expr::Unary(*op, child(operand)),
+            Expr::Binary(op, left, right) => {
+                let left = child(left);
+                Expr::Binary(*op, left, child(right))
+            }
+            Expr::Ternary(condition, then, otherwise) => {
+                let condition = child(condition);
+                let then = child(then);
+                Expr::Ternary(condition, then, child(otherwise))
+            }
+        }
+    }
+
+    let mut index = index;
+    visit(expr, &mut index, replace)
+}
+
+/// Listen to a formula at a few places, as an 8 bits bytebeat, rejecting silence, constant values
+/// and noise.
+pub fn check_candidate(expr: &Expr) -> Result<(), Rejection> {
+    let mut formula = ExprFormula::new(&expr.to_string()).map_err(|_| Rejection::Silent)?;
+
+    let mut changes = 0;
+    let mut noisy_windows = 0;
+    for start in WINDOWS {
+        let values: Vec<f64> = (start..start + WINDOW_LENGTH)
+            .map(|t| (formula.eval(t)[0] & 255) as f64)
+            .collect();
+        changes += values.windows(2).filter(|pair| pair[0] != pair[1]).count();
+
+        if max_autocorrelation(&values).map_or(false, |correlation| correlation < 0.25) {
+            noisy_windows += 1;
+        }
+    }
+
+    let total = WINDOWS.len() * WINDOW_LENGTH as usize;
+    if changes < total / 100 {
+        Err(Rejection::Silent)
+    } else if noisy_windows > WINDOWS.len() / 2 {
+        Err(Rejection::Noise)
+    } else {
+        Ok(())
+    }
+}
+
+/// Strongest normalized autocorrelation up to `MAX_LAG`, or `None` for constant values.
+fn max_autocorrelation(values: &[f64]) -> Option<f64> {
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    let centered: Vec<f64> = values.iter().map(|v| v - mean).collect();
+    let energy: f64 = centered.iter().map(|v| v * v).sum();
+    if energy == 0.0 {
+        return None;
+    }
+
+    (1..=MAX_LAG)
+        .map(|lag| {
+            let sum: f64 = centered
+                .iter()
+                .zip(&centered[lag..])
+                .map(|(a, b)| a * b)
+                .sum();
+            sum / energy
+        })
+        .reduce(f64::max)
+}
@@ -0,0 +1,213 @@
+#[cfg(feature = "jit")]
+mod jit;
+mod lexer;
+mod parser;
+mod vm;
+
+use std::{fmt, iter};
+
+#[cfg(feature = "jit")]
+pub use jit::JitFormula;
+pub use parser::{BinaryOp, Expr, UnaryOp};
+use vm::Instruction;
+
+use crate::{FormulaError, INPUT_NAMES};
+
+/// A bytebeats formula in the classic C syntax, compiled to bytecode.
+#[derive(Debug, Clone)]
+pub struct ExprFormula {
+    channels: Vec<Expr>,
+    code: Vec<Vec<Instruction>>,
+    stack: Vec<i32>,
+    /// `t`, then the inputs.
+    variables: [i32; INPUT_NAMES.len() + 1],
+}
+
+impl ExprFormula {
+    pub fn new(formula: &str) -> Result<Self, FormulaError> {
+        let names: Vec<_> = iter::once("t").chain(INPUT_NAMES).collect();
+        let channels = parser::parse(formula, &names)?;
+        let code: Vec<_> = channels
+            .iter()
+            .map(|expr| {
+                let mut code = Vec::new();
+                vm::compile(expr, &mut code);
+                code
+            })
+            .collect();
+        let depth = code.iter().map(|code| vm::stack_depth(code)).max();
+        let stack = Vec::with_capacity(depth.unwrap_or_default());
+
+        Ok(Self {
+            channels,
+            code,
+            stack,
+            variables: Default::default(),
+        })
+    }
+
+    /// The parsed expression of each channel, a single one for mono formulas.
+    pub fn channels(&self) -> &[Expr] {
+        &self.channels
+    }
+
+    /// Set the values of the `INPUT_NAMES` variables, rounded to integers.
+    pub fn set_inputs(&mut self, inputs: &[f64; INPUT_NAMES.len()]) {
+        for (variable, input) in self.variables[1..].iter_mut().zip(inputs) {
+            *variable = input.round() as i32;
+        }
+    }
+
+    /// The left and right values at `t`.
+    pub fn eval(&mut self, t: u32) -> [u32; 2] {
+        self.variables[0] = t as i32;
+        let variables = &self.variables;
+        let left = vm::run(&self.code[0], variables, &mut self.stack) as u32;
+        match self.code.get(1) {
+            Some(code) => [left, vm::run(code, variables, &mut self.stack) as u32],
+            None => [left, left],
+        }
+    }
+
+    /// Evaluate the formula for each time in `times`, wrapped to 32 bits.
+    pub fn eval_block(&mut self, times: &[f64], output: &mut [[f64; 2]]) {
+        for (t, value) in times.iter().zip(output.iter_mut()) {
+            let [left, right] = self.eval(*t as i64 as u32);
+            *value = [left as f64, right as f64];
+        }
+    }
+}
+
+/// Precedence of unary operators, above all binary ones.
+const UNARY_PRECEDENCE: u8 = 11;
+
+/// Formats an expression as a C formula, with only the parentheses it needs.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_expr(f, self, 0)
+    }
+}
+
+/// Write `expr` where operators binding less than `min_precedence` need parentheses.
+fn write_expr(f: &mut fmt::Formatter<'_>, expr: &Expr, min_precedence: u8) -> fmt::Result {
+    let precedence = match expr {
+        Expr::Number(value) if *value < 0 => 0,
+        Expr::Binary(op, ..) => op.precedence(),
+        Expr::Ternary(..) => 0,
+        _ => UNARY_PRECEDENCE,
+    };
+    if precedence < min_precedence {
+        write!(f, "(")?;
+    }
+
+    match expr {
+        Expr::Number(value) => write!(f, "{value}")?,
+        Expr::Var(0) => write!(f, "t")?,
+        Expr::Var(index) => write!(f, "{}", INPUT_NAMES[index - 1])?,
+        Expr::Unary(op, operand) => {
+            write!(f, "{}", op.symbol())?;
+            write_expr(f, operand, UNARY_PRECEDENCE)?;
+        }
+        Expr::Binary(op, left, right) => {
+            // Operators are left-associative
+            write_expr(f, left, precedence)?;
+            write!(f, "{}", op.symbol())?;
+            write_expr(f, right, precedence + 1)?;
+        }
+        Expr::Ternary(condition, then, otherwise) => {
+            write_expr(f, condition, 1)?;
+            write!(f, "?")?;
+            write_expr(f, then, 0)?;
+            write!(f, ":")?;
+            write_expr(f, otherwise, 0)?;
+        }
+    }
+
+    if precedence < min_precedence {
+        write!(f, ")")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(formula: &str, t: u32) -> i32 {
+        ExprFormula::new(formula).unwrap().eval(t)[0] as i32
+    }
+
+    fn code(formula: &str) -> Vec<Instruction> {
+        let mut code = vec![];
+        vm::compile(&parser::parse(formula, &["t"]).unwrap()[0], &mut code);
+        code
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("1+2*3", 0), 7);
+        assert_eq!(eval("1<<2+1", 0), 8);
+        assert_eq!(eval("6&3|8", 0), 10);
+        assert_eq!(eval("1|2^3&5", 0), 3);
+        assert_eq!(eval("10-4-3", 0), 3);
+        assert_eq!(eval("2<3==1", 0), 1);
+        assert_eq!(eval("-t%3", 7), -1);
+        assert_eq!(eval("!t+1", 0), 2);
+        assert_eq!(eval("0?1:0?2:3", 0), 3);
+        assert_eq!(eval("t>1&&t<5||t==9", 9), 1);
+    }
+
+    #[test]
+    fn wrapping_operators() {
+        assert_eq!(eval("2147483647+1", 0), i32::MIN);
+        assert_eq!(eval("(-2147483647-1)/-1", 0), i32::MIN);
+        assert_eq!(eval("(-2147483647-1)%-1", 0), 0);
+        assert_eq!(eval("t*t", 65_536), 0);
+        assert_eq!(eval("1<<33", 0), 2);
+        assert_eq!(eval("-1>>28", 0), -1);
+        assert_eq!(eval("-1>>>28", 0), 15);
+        assert_eq!(eval("~t", 0), -1);
+        assert_eq!(eval("t", u32::MAX), -1);
+    }
+
+    #[test]
+    fn division_by_zero() {
+        assert_eq!(eval("t/0", 5), 0);
+        assert_eq!(eval("t%0", 5), 0);
+        assert_eq!(eval("t/(t&0)+1", 5), 1);
+    }
+
+    #[test]
+    fn constant_folding() {
+        use Instruction::*;
+        assert_eq!(code("(1<<4)-1"), [Push(15)]);
+        assert_eq!(code("t+2*3"), [Load(0), Push(6), Binary(BinaryOp::Add)]);
+        assert_eq!(code("0?t:5"), [Push(5)]);
+        // Only whole constant subexpressions are folded
+        assert_eq!(code("1?t:5"), [Push(1), Load(0), Push(5), Select]);
+    }
+
+    #[test]
+    fn display_round_trip() {
+        for formula in [
+            "t*(t>>5|t>>8)",
+            "(t>>7|t|t>>6)*10+4*(t&t>>13|t>>6)",
+            "t>1?-t:~t%3",
+        ] {
+            let expr = &parser::parse(formula, &["t"]).unwrap()[0];
+            assert_eq!(expr.to_string(), formula);
+        }
+    }
+
+    #[test]
+    fn deep_formulas_are_rejected() {
+        let nested = |depth| format!("{}t{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(ExprFormula::new(&nested(50)).is_ok());
+        assert!(ExprFormula::new(&nested(100_000)).is_err());
+        assert!(ExprFormula::new(&format!("{}t", "-".repeat(100_000))).is_err());
+        assert!(ExprFormula::new(&format!("{}t", "+".repeat(100_000))).is_err());
+        assert!(ExprFormula::new(&format!("{}t", "t+".repeat(100_000))).is_err());
+        assert!(ExprFormula::new(&format!("{}t", "t?t:".repeat(100_000))).is_err());
+        assert!(ExprFormula::new(&format!("{}t", "t+".repeat(200))).is_ok());
+    }
+}
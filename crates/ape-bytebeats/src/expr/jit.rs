@@ -0,0 +1,234 @@
+use std::{fmt::Display, mem};
+
+use cranelift_codegen::{
+    ir::{condcodes::IntCC, types, AbiParam, InstBuilder, MemFlags, Value},
+    settings::{self, Configurable},
+};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, FuncId, Linkage, Module};
+
+use super::{vm::Instruction, BinaryOp, ExprFormula, UnaryOp};
+use crate::{FormulaError, INPUT_NAMES};
+
+type FormulaFn = extern "C" fn(*const i32) -> i32;
+
+/// A C formula compiled to native code, giving the same results as the bytecode interpreter.
+pub struct JitFormula {
+    module: Option<Box<JITModule>>,
+    /// One function per channel.
+    functions: Vec<FormulaFn>,
+    /// `t`, then the inputs.
+    variables: [i32; INPUT_NAMES.len() + 1],
+}
+
+// The module is only kept to free the code memory, and never used from several threads
+unsafe impl Send for JitFormula {}
+
+impl JitFormula {
+    pub fn new(formula: &ExprFormula) -> Result<Self, FormulaError> {
+        let mut flags = settings::builder();
+        flags.set("opt_level", "speed").map_err(jit_error)?;
+        let isa = cranelift_native::builder()
+            .map_err(jit_error)?
+            .finish(settings::Flags::new(flags))
+            .map_err(jit_error)?;
+        let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+
+        let ids = formula
+            .code
+            .iter()
+            .enumerate()
+            .map(|(channel, code)| define_function(&mut module, &format!("channel{channel}"), code))
+            .collect::<Result<Vec<_>, _>>()?;
+        module.finalize_definitions();
+
+        // Safety: the code was built with the signature of `FormulaFn`
+        let functions = ids
+            .into_iter()
+            .map(|id| unsafe { mem::transmute::<_, FormulaFn>(module.get_finalized_function(id)) })
+            .collect();
+
+        Ok(Self {
+            module: Some(Box::new(module)),
+            functions,
+            variables: formula.variables,
+        })
+    }
+
+    /// Set the values of the `INPUT_NAMES` variables, rounded to integers.
+    pub fn set_inputs(&mut self, inputs: &[f64; INPUT_NAMES.len()]) {
+        for (variable, input) in self.variables[1..].iter_mut().zip(inputs) {
+            *variable = input.round() as i32;
+        }
+    }
+
+    /// The left and right values at `t`.
+    pub fn eval(&mut self, t: u32) -> [u32; 2] {
+        self.variables[0] = t as i32;
+        let variables = self.variables.as_ptr();
+        let left = (self.functions[0])(variables) as u32;
+        match self.functions.get(1) {
+            Some(right) => [left, right(variables) as u32],
+            None => [left, left],
+        }
+    }
+
+    /// Evaluate the formula for each time in `times`, wrapped to 32 bits.
+    pub fn eval_block(&mut self, times: &[f64], output: &mut [[f64; 2]]) {
+        for (t, value) in times.iter().zip(output.iter_mut()) {
+            let [left, right] = self.eval(*t as i64 as u32);
+            *value = [left as f64, right as f64];
+        }
+    }
+}
+
+impl Drop for JitFormula {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            // Safety: the function pointers are dropped along with the module
+            unsafe { module.free_memory() };
+        }
+    }
+}
+
+fn define_function(
+    module: &mut JITModule,
+    name: &str,
+    code: &[Instruction],
+) -> Result<FuncId, FormulaError> {
+    let mut ctx = module.make_context();
+    let pointer = module.target_config().pointer_type();
+    ctx.func.signature.params.push(AbiParam::new(pointer));
+    ctx.func.signature.returns.push(AbiParam::new(types::I32));
+
+    let mut builder_ctx = FunctionBuilderContext::new();
+    let mut builder = FunctionBuilder::new(&mut ctx.func, &mut builder_ctx);
+    let block = builder.create_block();
+    builder.append_block_params_for_function_params(block);
+    builder.switch_to_block(block);
+    builder.seal_block(block);
+
+    let variables = builder.block_params(block)[0];
+    let mut stack = Vec::new();
+    for instruction in code {
+        let value = match *instruction {
+            Instruction::Push(value) => builder.ins().iconst(types::I32, value as i64),
+            Instruction::Load(index) => builder.ins().load(
+                types::I32,
+                MemFlags::trusted(),
+                variables,
+                (index * mem::size_of::<i32>()) as i32,
+            ),
+            Instruction::Unary(op) => {
+                let a = pop(&mut stack)?;
+                unary(&mut builder, op, a)
+            }
+            Instruction::Binary(op) => {
+                let b = pop(&mut stack)?;
+                let a = pop(&mut stack)?;
+                binary(&mut builder, op, a, b)
+            }
+            Instruction::Select => {
+                let otherwise = pop(&mut stack)?;
+                let then = pop(&mut stack)?;
+                let condition = pop(&mut stack)?;
+                builder.ins().select(condition, then, otherwise)
+            }
+        };
+        stack.push(value);
+    }
+
+    let result = pop(&mut stack)?;
+    builder.ins().return_(&[result]);
+    builder.finalize();
+
+    let id = module
+        .declare_function(name, Linkage::Export, &ctx.func.signature)
+        .map_err(jit_error)?;
+    module.define_function(id, &mut ctx).map_err(jit_error)?;
+    module.clear_context(&mut ctx);
+    Ok(id)
+}
+
+fn jit_error(error: impl Display) -> FormulaError {
+    FormulaError::new(format!(
+        "could not compile the formula to native code: {error}"
+    ))
+}
+
+fn pop(stack: &mut Vec<Value>) -> Result<Value, FormulaError> {
+    stack
+        .pop()
+        .ok_or_else(|| FormulaError::new("invalid formula bytecode"))
+}
+
+fn unary(builder: &mut FunctionBuilder, op: UnaryOp, a: Value) -> Value {
+    match op {
+        UnaryOp::Neg => builder.ins().ineg(a),
+        UnaryOp::BitNot => builder.ins().bnot(a),
+        UnaryOp::Not => {
+            let zero = builder.ins().icmp_imm(IntCC::Equal, a, 0);
+            builder.ins().bint(types::I32, zero)
+        }
+    }
+}
+
+/// Mirrors `BinaryOp::apply`, avoiding the division traps.
+fn binary(builder: &mut FunctionBuilder, op: BinaryOp, a: Value, b: Value) -> Value {
+    let compare = |builder: &mut FunctionBuilder, cc| {
+        let flag = builder.ins().icmp(cc, a, b);
+        builder.ins().bint(types::I32, flag)
+    };
+    let truth = |builder: &mut FunctionBuilder, v| builder.ins().icmp_imm(IntCC::NotEqual, v, 0);
+
+    match op {
+        BinaryOp::Add => builder.ins().iadd(a, b),
+        BinaryOp::Sub => builder.ins().isub(a, b),
+        BinaryOp::Mul => builder.ins().imul(a, b),
+        BinaryOp::Div => {
+            // Divide by one instead of zero, or instead of -1 when it would overflow
+            let zero = builder.ins().icmp_imm(IntCC::Equal, b, 0);
+            let minus_one = builder.ins().icmp_imm(IntCC::Equal, b, -1);
+            let min = builder.ins().icmp_imm(IntCC::Equal, a, i32::MIN as i64);
+            let overflow = builder.ins().band(minus_one, min);
+            let replace = builder.ins().bor(zero, overflow);
+            let one = builder.ins().iconst(types::I32, 1);
+            let divisor = builder.ins().select(replace, one, b);
+            let quotient = builder.ins().sdiv(a, divisor);
+            let nothing = builder.ins().iconst(types::I32, 0);
+            builder.ins().select(zero, nothing, quotient)
+        }
+        BinaryOp::Rem => {
+            // Any number modulo one, like modulo zero or -1, gives zero
+            let zero = builder.ins().icmp_imm(IntCC::Equal, b, 0);
+            let minus_one = builder.ins().icmp_imm(IntCC::Equal, b, -1);
+            let replace = builder.ins().bor(zero, minus_one);
+            let one = builder.ins().iconst(types::I32, 1);
+            let divisor = builder.ins().select(replace, one, b);
+            builder.ins().srem(a, divisor)
+        }
+        BinaryOp::Shl => builder.ins().ishl(a, b),
+        BinaryOp::Shr => builder.ins().sshr(a, b),
+        BinaryOp::UShr => builder.ins().ushr(a, b),
+        BinaryOp::Lt => compare(builder, IntCC::SignedLessThan),
+        BinaryOp::Le => compare(builder, IntCC::SignedLessThanOrEqual),
+        BinaryOp::Gt => compare(builder, IntCC::SignedGreaterThan),
+        BinaryOp::Ge => compare(builder, IntCC::SignedGreaterThanOrEqual),
+        BinaryOp::Eq => compare(builder, IntCC::Equal),
+        BinaryOp::Ne => compare(builder, IntCC::NotEqual),
+        BinaryOp::BitAnd => builder.ins().band(a, b),
+        BinaryOp::BitXor => builder.ins().bxor(a, b),
+        BinaryOp::BitOr => builder.ins().bor(a, b),
+        BinaryOp::And | BinaryOp::Or => {
+            let a = truth(builder, a);
+            let b = truth(builder, b);
+            let flag = if op == BinaryOp::And {
+                builder.ins().band(a, b)
+            } else {
+                builder.ins().bor(a, b)
+            };
+            builder.ins().bint(types::I32, flag)
+        }
+    }
+}
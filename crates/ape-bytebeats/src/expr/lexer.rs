@@ -0,0 +1,67 @@
+use crate::FormulaError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    Number(i32),
+    Ident(String),
+    Symbol(&'static str),
+}
+
+/// Operators, longest first so that `>>>` is not read as `>>` then `>`.
+const SYMBOLS: [&str; 28] = [
+    ">>>", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "%", "<", ">", "&",
+    "^", "|", "!", "~", "?", ":", "(", ")", "[", "]", ",",
+];
+
+/// Split a formula into tokens, each with its byte position.
+pub fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, FormulaError> {
+    let bytes = source.as_bytes();
+    let mut tokens = Vec::new();
+    let mut position = 0;
+
+    while position < bytes.len() {
+        let c = bytes[position];
+        let start = position;
+
+        if c.is_ascii_whitespace() {
+            position += 1;
+        } else if c.is_ascii_digit() {
+            while position < bytes.len() && bytes[position].is_ascii_alphanumeric() {
+                position += 1;
+            }
+            let text = &source[start..position];
+            let value =
+                if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+                    u64::from_str_radix(hex, 16)
+                } else {
+                    text.parse()
+                };
+            match value {
+                // Literals wrap like everything else
+                Ok(value) => tokens.push((Token::Number(value as i32), start)),
+                Err(_) => return Err(FormulaError::at(start, format!("invalid number '{text}'"))),
+            }
+        } else if c.is_ascii_alphabetic() || c == b'_' {
+            while position < bytes.len()
+                && (bytes[position].is_ascii_alphanumeric() || bytes[position] == b'_')
+            {
+                position += 1;
+            }
+            tokens.push((Token::Ident(source[start..position].to_string()), start));
+        } else if let Some(symbol) = SYMBOLS
+            .iter()
+            .find(|symbol| source[start..].starts_with(**symbol))
+        {
+            position += symbol.len();
+            tokens.push((Token::Symbol(symbol), start));
+        } else {
+            let c = source[start..].chars().next().unwrap_or_default();
+            return Err(FormulaError::at(
+                start,
+                format!("unexpected character '{c}'"),
+            ));
+        }
+    }
+
+    Ok(tokens)
+}
@@ -0,0 +1,344 @@
+use super::lexer::{tokenize, Token};
+use crate::FormulaError;
+
+/// Deepest expression tree, keeping the recursive passes over expressions within the stack.
+const MAX_DEPTH: usize = 256;
+/// Deepest parser recursion, each parenthesis nesting the parser several times.
+const MAX_NESTING: usize = 4 * MAX_DEPTH;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    BitNot,
+}
+
+impl UnaryOp {
+    pub fn apply(self, a: i32) -> i32 {
+        match self {
+            Self::Neg => a.wrapping_neg(),
+            Self::Not => (a == 0) as i32,
+            Self::BitNot => !a,
+        }
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Self::Neg => "-",
+            Self::Not => "!",
+            Self::BitNot => "~",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Shl,
+    Shr,
+    UShr,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    BitAnd,
+    BitXor,
+    BitOr,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    /// C operators with 32 bits wraparound, shifts taking the low 5 bits of their count,
+    /// and a division by zero giving zero.
+    pub fn apply(self, a: i32, b: i32) -> i32 {
+        match self {
+            Self::Add => a.wrapping_add(b),
+            Self::Sub => a.wrapping_sub(b),
+            Self::Mul => a.wrapping_mul(b),
+            Self::Div if b == 0 => 0,
+            Self::Div => a.wrapping_div(b),
+            Self::Rem if b == 0 => 0,
+            Self::Rem => a.wrapping_rem(b),
+            Self::Shl => a.wrapping_shl(b as u32),
+            Self::Shr => a.wrapping_shr(b as u32),
+            Self::UShr => (a as u32).wrapping_shr(b as u32) as i32,
+            Self::Lt => (a < b) as i32,
+            Self::Le => (a <= b) as i32,
+            Self::Gt => (a > b) as i32,
+            Self::Ge => (a >= b) as i32,
+            Self::Eq => (a == b) as i32,
+            Self::Ne => (a != b) as i32,
+            Self::BitAnd => a & b,
+            Self::BitXor => a ^ b,
+            Self::BitOr => a | b,
+            Self::And => (a != 0 && b != 0) as i32,
+            Self::Or => (a != 0 || b != 0) as i32,
+        }
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Mul => "*",
+            Self::Div => "/",
+            Self::Rem => "%",
+            Self::Shl => "<<",
+            Self::Shr => ">>",
+            Self::UShr => ">>>",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+            Self::Eq => "==",
+            Self::Ne => "!=",
+            Self::BitAnd => "&",
+            Self::BitXor => "^",
+            Self::BitOr => "|",
+            Self::And => "&&",
+            Self::Or => "||",
+        }
+    }
+
+    /// Binding strength, from 1 for `||` to 10 for `*`.
+    pub fn precedence(self) -> u8 {
+        Self::from_symbol(self.symbol()).map_or(0, |(_, precedence)| precedence)
+    }
+
+    fn from_symbol(symbol: &str) -> Option<(Self, u8)> {
+        Some(match symbol {
+            "||" => (Self::Or, 1),
+            "&&" => (Self::And, 2),
+            "|" => (Self::BitOr, 3),
+            "^" => (Self::BitXor, 4),
+            "&" => (Self::BitAnd, 5),
+            "==" => (Self::Eq, 6),
+            "!=" => (Self::Ne, 6),
+            "<" => (Self::Lt, 7),
+            "<=" => (Self::Le, 7),
+            ">" => (Self::Gt, 7),
+            ">=" => (Self::Ge, 7),
+            "<<" => (Self::Shl, 8),
+            ">>" => (Self::Shr, 8),
+            ">>>" => (Self::UShr, 8),
+            "+" => (Self::Add, 9),
+            "-" => (Self::Sub, 9),
+            "*" => (Self::Mul, 10),
+            "/" => (Self::Div, 10),
+            "%" => (Self::Rem, 10),
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Number(i32),
+    Var(usize),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Ternary(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+struct Parser<'a> {
+    tokens: Vec<(Token, usize)>,
+    index: usize,
+    end: usize,
+    variables: &'a [&'a str],
+    /// Productions being parsed, growing with parentheses and prefix operators.
+    nesting: usize,
+}
+
+/// A parsed expression, with the depth of its tree.
+type Parsed = Result<(Expr, usize), FormulaError>;
+
+/// Parse a formula, with identifiers resolved to their index in `variables`.
+/// A formula is either one expression, or a `[left, right]` pair of expressions.
+pub fn parse(source: &str, variables: &[&str]) -> Result<Vec<Expr>, FormulaError> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?,
+        index: 0,
+        end: source.len(),
+        variables,
+        nesting: 0,
+    };
+
+    let channels = if parser.peek_symbol() == Some("[") {
+        parser.index += 1;
+        let (left, _) = parser.ternary()?;
+        parser.expect(",")?;
+        let (right, _) = parser.ternary()?;
+        parser.expect("]")?;
+        vec![left, right]
+    } else {
+        vec![parser.ternary()?.0]
+    };
+
+    if let Some((token, position)) = parser.tokens.get(parser.index) {
+        return Err(FormulaError::at(
+            *position,
+            format!("unexpected {}", describe(token)),
+        ));
+    }
+
+    Ok(channels)
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Number(n) => format!("number {n}"),
+        Token::Ident(name) => format!("'{name}'"),
+        Token::Symbol(symbol) => format!("'{symbol}'"),
+    }
+}
+
+impl<'a> Parser<'a> {
+    fn position(&self) -> usize {
+        self.tokens
+            .get(self.index)
+            .map_or(self.end, |(_, position)| *position)
+    }
+
+    fn check_depth(&self, depth: usize) -> Result<usize, FormulaError> {
+        if depth > MAX_DEPTH {
+            return Err(FormulaError::at(
+                self.position(),
+                format!("the formula is more than {MAX_DEPTH} operations deep"),
+            ));
+        }
+        Ok(depth)
+    }
+
+    fn enter(&mut self) -> Result<(), FormulaError> {
+        self.nesting += 1;
+        if self.nesting > MAX_NESTING {
+            return Err(FormulaError::at(
+                self.position(),
+                "the formula is nested too deeply",
+            ));
+        }
+        Ok(())
+    }
+
+    fn peek_symbol(&self) -> Option<&'static str> {
+        match self.tokens.get(self.index) {
+            Some((Token::Symbol(symbol), _)) => Some(symbol),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), FormulaError> {
+        if self.peek_symbol() == Some(symbol) {
+            self.index += 1;
+            return Ok(());
+        }
+
+        Err(match self.tokens.get(self.index) {
+            Some((token, position)) => FormulaError::at(
+                *position,
+                format!("expected '{symbol}' but found {}", describe(token)),
+            ),
+            None => FormulaError::at(self.end, format!("expected '{symbol}'")),
+        })
+    }
+
+    fn ternary(&mut self) -> Parsed {
+        self.enter()?;
+        let (condition, condition_depth) = self.binary(1)?;
+        if self.peek_symbol() != Some("?") {
+            self.nesting -= 1;
+            return Ok((condition, condition_depth));
+        }
+
+        self.index += 1;
+        let (then, then_depth) = self.ternary()?;
+        self.expect(":")?;
+        let (otherwise, otherwise_depth) = self.ternary()?;
+        let depth = self.check_depth(condition_depth.max(then_depth).max(otherwise_depth) + 1)?;
+        self.nesting -= 1;
+        Ok((
+            Expr::Ternary(Box::new(condition), Box::new(then), Box::new(otherwise)),
+            depth,
+        ))
+    }
+
+    /// Precedence climbing over left-associative binary operators.
+    fn binary(&mut self, min_precedence: u8) -> Parsed {
+        self.enter()?;
+        let (mut left, mut depth) = self.unary()?;
+        while let Some((op, precedence)) = self.peek_symbol().and_then(BinaryOp::from_symbol) {
+            if precedence < min_precedence {
+                break;
+            }
+
+            self.index += 1;
+            let (right, right_depth) = self.binary(precedence + 1)?;
+            // Chains of operators deepen the tree without nesting the parser
+            depth = self.check_depth(depth.max(right_depth) + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+
+        self.nesting -= 1;
+        Ok((left, depth))
+    }
+
+    fn unary(&mut self) -> Parsed {
+        self.enter()?;
+        let op = match self.peek_symbol() {
+            Some("-") => Some(UnaryOp::Neg),
+            Some("!") => Some(UnaryOp::Not),
+            Some("~") => Some(UnaryOp::BitNot),
+            Some("+") => None,
+            _ => {
+                let parsed = self.primary()?;
+                self.nesting -= 1;
+                return Ok(parsed);
+            }
+        };
+
+        self.index += 1;
+        let (operand, depth) = self.unary()?;
+        self.nesting -= 1;
+        Ok(match op {
+            Some(op) => (
+                Expr::Unary(op, Box::new(operand)),
+                self.check_depth(depth + 1)?,
+            ),
+            None => (operand, depth),
+        })
+    }
+
+    fn primary(&mut self) -> Parsed {
+        let (token, position) = match self.tokens.get(self.index) {
+            Some(token) => token.clone(),
+            None => return Err(FormulaError::at(self.end, "unexpected end of formula")),
+        };
+        self.index += 1;
+
+        match token {
+            Token::Number(n) => Ok((Expr::Number(n), 1)),
+            Token::Ident(name) => self
+                .variables
+                .iter()
+                .position(|v| *v == name)
+                .map(|index| (Expr::Var(index), 1))
+                .ok_or_else(|| FormulaError::at(position, format!("unknown variable '{name}'"))),
+            Token::Symbol("(") => {
+                let parsed = self.ternary()?;
+                self.expect(")")?;
+                Ok(parsed)
+            }
+            token => Err(FormulaError::at(
+                position,
+                format!("unexpected {}", describe(&token)),
+            )),
+        }
+    }
+}
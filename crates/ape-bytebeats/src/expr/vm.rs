@@ -0,0 +1,99 @@
+use super::parser::{BinaryOp, Expr, UnaryOp};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Push(i32),
+    Load(usize),
+    Unary(UnaryOp),
+    Binary(BinaryOp),
+    /// Pop the value if false, the value if true and the condition, push the selected value.
+    Select,
+}
+
+/// Flatten an expression into stack machine code, folding constant parts.
+pub fn compile(expr: &Expr, code: &mut Vec<Instruction>) {
+    if let Some(value) = fold(expr) {
+        code.push(Instruction::Push(value));
+        return;
+    }
+
+    match expr {
+        Expr::Number(n) => code.push(Instruction::Push(*n)),
+        Expr::Var(index) => code.push(Instruction::Load(*index)),
+        Expr::Unary(op, a) => {
+            compile(a, code);
+            code.push(Instruction::Unary(*op));
+        }
+        Expr::Binary(op, a, b) => {
+            compile(a, code);
+            compile(b, code);
+            code.push(Instruction::Binary(*op));
+        }
+        Expr::Ternary(condition, then, otherwise) => {
+            compile(condition, code);
+            compile(then, code);
+            compile(otherwise, code);
+            code.push(Instruction::Select);
+        }
+    }
+}
+
+fn fold(expr: &Expr) -> Option<i32> {
+    match expr {
+        Expr::Number(n) => Some(*n),
+        Expr::Var(_) => None,
+        Expr::Unary(op, a) => Some(op.apply(fold(a)?)),
+        Expr::Binary(op, a, b) => Some(op.apply(fold(a)?, fold(b)?)),
+        Expr::Ternary(condition, then, otherwise) => {
+            if fold(condition)? != 0 {
+                fold(then)
+            } else {
+                fold(otherwise)
+            }
+        }
+    }
+}
+
+/// Maximum stack depth reached by the code.
+pub fn stack_depth(code: &[Instruction]) -> usize {
+    let mut depth = 0usize;
+    let mut max = 0;
+    for instruction in code {
+        match instruction {
+            Instruction::Push(_) | Instruction::Load(_) => depth += 1,
+            Instruction::Unary(_) => (),
+            Instruction::Binary(_) => depth -= 1,
+            Instruction::Select => depth -= 2,
+        }
+        max = Ord::max(max, depth);
+    }
+    max
+}
+
+/// Run the code with the given variables, `stack` being large enough not to reallocate.
+pub fn run(code: &[Instruction], variables: &[i32], stack: &mut Vec<i32>) -> i32 {
+    stack.clear();
+    for instruction in code {
+        match *instruction {
+            Instruction::Push(value) => stack.push(value),
+            Instruction::Load(index) => stack.push(variables[index]),
+            Instruction::Unary(op) => {
+                let a = stack.pop().unwrap_or_default();
+                stack.push(op.apply(a));
+            }
+            Instruction::Binary(op) => {
+                let b = stack.pop().unwrap_or_default();
+                let a = stack.pop().unwrap_or_default();
+                stack.push(op.apply(a, b));
+            }
+            Instruction::Select => {
+                let otherwise = stack.pop().unwrap_or_default();
+                let then = stack.pop().unwrap_or_default();
+                let condition = stack.pop().unwrap_or_default();
+                stack.push(if condition != 0 { then } else { otherwise });
+            }
+        }
+    }
+
+    stack.pop().unwrap_or_default()
+}
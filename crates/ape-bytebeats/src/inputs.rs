@@ -0,0 +1,58 @@
+use ape_core::params::Param;
+
+/// Names of the variables given to formulas besides `t`, in the order of `InputValues::variables`.
+pub const INPUT_NAMES: [&str; 13] = [
+    "a", "b", "c", "d", "e", "f", "g", "h", "freq", "vel", "mx", "my", "sr",
+];
+
+/// Values a formula can play with, besides `t`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct InputValues {
+    /// User sliders `a` to `h`, from 0 to 255.
+    pub sliders: [f64; 8],
+    /// Frequency of the note being played, in Hz.
+    pub freq: f64,
+    /// Velocity of the note being played, from 0 to 127, or 0 when no note plays.
+    pub velocity: f64,
+    /// Mouse position, from 0 to 255.
+    pub mouse: [f64; 2],
+}
+
+impl InputValues {
+    /// Values of the `INPUT_NAMES` variables, `sr` being the formula rate.
+    pub fn variables(&self, rate: u32) -> [f64; INPUT_NAMES.len()] {
+        let mut variables = [0.0; INPUT_NAMES.len()];
+        variables[..8].copy_from_slice(&self.sliders);
+        variables[8] = self.freq;
+        variables[9] = self.velocity;
+        variables[10] = self.mouse[0];
+        variables[11] = self.mouse[1];
+        variables[12] = rate as f64;
+        variables
+    }
+}
+
+/// Formula inputs shared between control threads and the audio thread.
+#[derive(Clone, Default)]
+pub struct FormulaInputs {
+    pub sliders: [Param; 8],
+    pub freq: Param,
+    pub velocity: Param,
+    pub mouse: [Param; 2],
+}
+
+impl FormulaInputs {
+    pub fn set_note(&self, freq: f64, velocity: f64) {
+        self.freq.set(freq);
+        self.velocity.set(velocity);
+    }
+
+    pub fn values(&self) -> InputValues {
+        InputValues {
+            sliders: std::array::from_fn(|index| self.sliders[index].get()),
+            freq: self.freq.get(),
+            velocity: self.velocity.get(),
+            mouse: [self.mouse[0].get(), self.mouse[1].get()],
+        }
+    }
+}
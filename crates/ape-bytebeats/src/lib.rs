@@ -1,40 +1,367 @@
-use ape_core::{color_eyre::eyre, process_stream, AudioOutput};
-use rlua::Lua;
-
-fn bytebeats_to_f32(v: u32) -> f32 {
-    (v & 255) as f32 / 127.0 - 1.0
-}
-
-pub fn run_bytebeats_synth(output: AudioOutput, formula: String) -> eyre::Result<()> {
-    let sample_rate = output.sample_rate();
-    let resample_ratio = 8_000.0 / sample_rate as f32;
-    let lua = Lua::new();
-
-    lua.context(|ctx| {
-        let globs = ctx.globals();
-        globs.set("t", 0).unwrap();
-    });
-
-    let mut count = 0.0;
-    let sample_fn = move || {
-        let t = count as u32;
-
-        let output = lua.context(|ctx| {
-            let globs = ctx.globals();
-            globs
-                .set("t", t)
-                .expect("could not update the 't' variable");
-            ctx.load(&formula)
-                .eval()
-                .expect("could not evaluate the formula")
-        });
-
-        let f = bytebeats_to_f32(output);
-        count += resample_ratio;
-        vec![f, f]
+mod error;
+mod explore;
+mod expr;
+mod inputs;
+mod lua;
+mod mode;
+mod node;
+mod period;
+mod playlist;
+mod preset;
+mod script;
+mod share;
+
+use std::{fmt::Display, str::FromStr};
+
+use ape_core::{
+    color_eyre::eyre::{self, eyre},
+    process_stream, tracing, AudioOutput,
+};
+
+pub use error::FormulaError;
+pub use explore::{check_candidate, Explorer, Rejection};
+#[cfg(feature = "jit")]
+pub use expr::JitFormula;
+pub use expr::{BinaryOp, Expr, ExprFormula, UnaryOp};
+pub use inputs::{FormulaInputs, InputValues, INPUT_NAMES};
+pub use lua::LuaFormula;
+pub use mode::OutputMode;
+pub use node::{bytebeat, BytebeatNode};
+pub use period::{detect_period, export_loop, MAX_PERIOD};
+pub use playlist::{run_playlist, Playlist};
+pub use preset::{Preset, PresetLibrary};
+pub use script::{run_script_synth, LuaScript, ScriptGenerator};
+pub use share::{decode_share_url, encode_share_url};
+
+const BLOCK_SIZE: usize = 512;
+
+/// Language of a formula.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    /// C/JavaScript expressions, with 32 bits integer wraparound.
+    C,
+    #[default]
+    Lua,
+}
+
+impl Display for Dialect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::C => "c",
+                Self::Lua => "lua",
+            }
+        )
+    }
+}
+
+impl FromStr for Dialect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "c" => Ok(Self::C),
+            "lua" => Ok(Self::Lua),
+            other => Err(format!("unknown formula language '{other}'")),
+        }
+    }
+}
+
+/// A compiled formula in any dialect.
+pub enum Formula {
+    C(ExprFormula),
+    #[cfg(feature = "jit")]
+    Jit(JitFormula),
+    Lua(LuaFormula),
+}
+
+impl Formula {
+    pub fn new(formula: &str, options: &BytebeatOptions) -> Result<Self, FormulaError> {
+        Self::compile(formula, options).map_err(|error| error.in_formula(formula))
+    }
+
+    fn compile(formula: &str, options: &BytebeatOptions) -> Result<Self, FormulaError> {
+        let mode = options.mode;
+        let inputs = InputValues::default().variables(options.rate);
+        let mut formula = match options.dialect {
+            Dialect::C if mode.is_float() => {
+                return Err(FormulaError::new(format!(
+                    "the {mode} mode needs a Lua formula, C formulas only use integers"
+                )))
+            }
+            #[cfg(not(feature = "jit"))]
+            Dialect::C => Self::C(ExprFormula::new(formula)?),
+            #[cfg(feature = "jit")]
+            Dialect::C => {
+                let interpreted = ExprFormula::new(formula)?;
+                // Formulas the native backend can't compile are still interpreted
+                match JitFormula::new(&interpreted) {
+                    Ok(compiled) => Self::Jit(compiled),
+                    Err(error) => {
+                        tracing::warn!("could not compile the formula, interpreting it: {error}");
+                        Self::C(interpreted)
+                    }
+                }
+            }
+            Dialect::Lua => {
+                let float_time = mode == OutputMode::Funcbeat;
+                Self::Lua(LuaFormula::new(formula, float_time, &inputs)?)
+            }
+        };
+
+        formula.set_inputs(&inputs)?;
+        Ok(formula)
+    }
+
+    /// Set the values of the `INPUT_NAMES` variables.
+    pub fn set_inputs(&mut self, inputs: &[f64; INPUT_NAMES.len()]) -> Result<(), FormulaError> {
+        match self {
+            Self::C(formula) => formula.set_inputs(inputs),
+            #[cfg(feature = "jit")]
+            Self::Jit(formula) => formula.set_inputs(inputs),
+            Self::Lua(formula) => formula.set_inputs(inputs)?,
+        }
+        Ok(())
+    }
+
+    /// Evaluate the left and right values of the formula for each time in `times`.
+    pub fn eval_block(
+        &mut self,
+        times: &[f64],
+        output: &mut [[f64; 2]],
+    ) -> Result<(), FormulaError> {
+        match self {
+            Self::C(formula) => formula.eval_block(times, output),
+            #[cfg(feature = "jit")]
+            Self::Jit(formula) => formula.eval_block(times, output),
+            Self::Lua(formula) => formula.eval_block(times, output)?,
+        }
+        Ok(())
+    }
+}
+
+/// Parse a formula rate in Hz, with shortcuts like `8k`, `11k`, `22k` or `44.1k`.
+pub fn parse_rate(s: &str) -> Result<u32, String> {
+    let rate = match s.to_lowercase().as_str() {
+        "11k" => 11_025,
+        "22k" => 22_050,
+        "44k" => 44_100,
+        other => {
+            let (number, multiplier) = match other.strip_suffix('k') {
+                Some(number) => (number, 1_000.0),
+                None => (other, 1.0),
+            };
+            let rate: f64 = number.parse().map_err(|_| format!("invalid rate '{s}'"))?;
+            (rate * multiplier).round() as u32
+        }
     };
 
-    process_stream(output, sample_fn)?;
+    if rate == 0 {
+        return Err(format!("invalid rate '{s}'"));
+    }
+    Ok(rate)
+}
+
+/// Formulas played by a generator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BytebeatSource {
+    /// A mono formula, or one returning both channels.
+    Single(String),
+    /// A formula for the left channel, and another one for the right channel.
+    Split(String, String),
+}
+
+#[derive(Debug, Clone)]
+pub struct BytebeatOptions {
+    pub dialect: Dialect,
+    pub mode: OutputMode,
+    /// Formula sample rate, in Hz.
+    pub rate: u32,
+    /// Value of `t` when the playback starts.
+    pub start: u64,
+    /// Plays the right channel this many `t` ahead of the left one.
+    pub stereo_offset: u64,
+}
+
+impl Default for BytebeatOptions {
+    fn default() -> Self {
+        Self {
+            dialect: Dialect::default(),
+            mode: OutputMode::default(),
+            rate: 8_000,
+            start: 0,
+            stereo_offset: 0,
+        }
+    }
+}
+
+/// Plays a formula at its own rate, resampled to the output rate.
+pub struct BytebeatGenerator {
+    formula: Formula,
+    /// Formula of the right channel, when it is not the main one.
+    right: Option<Formula>,
+    mode: OutputMode,
+    rate: u32,
+    sample_rate: u32,
+    start: u64,
+    stereo_offset: u64,
+    inputs: InputValues,
+    /// Set when the inputs changed since the formulas last got them.
+    inputs_changed: bool,
+    /// Output frames played, `t` being derived from it exactly.
+    frame: u64,
+    times: [f64; BLOCK_SIZE],
+    right_times: [f64; BLOCK_SIZE],
+    values: [[f64; 2]; BLOCK_SIZE],
+    right_values: [[f64; 2]; BLOCK_SIZE],
+    index: usize,
+    /// Set while the current block failed and plays silence.
+    error: Option<FormulaError>,
+}
+
+impl BytebeatGenerator {
+    pub fn new(
+        source: &BytebeatSource,
+        options: &BytebeatOptions,
+        sample_rate: u32,
+    ) -> Result<Self, FormulaError> {
+        let compile = |formula: &str| Formula::new(formula, options);
+        let (formula, right) = match source {
+            BytebeatSource::Single(formula) => (compile(formula)?, None),
+            BytebeatSource::Split(left, right) => (compile(left)?, Some(compile(right)?)),
+        };
+
+        Ok(Self {
+            formula,
+            right,
+            mode: options.mode,
+            rate: options.rate,
+            sample_rate,
+            start: options.start,
+            stereo_offset: options.stereo_offset,
+            inputs: InputValues::default(),
+            inputs_changed: false,
+            frame: 0,
+            times: [0.0; BLOCK_SIZE],
+            right_times: [0.0; BLOCK_SIZE],
+            values: [[0.0; 2]; BLOCK_SIZE],
+            right_values: [[0.0; 2]; BLOCK_SIZE],
+            index: BLOCK_SIZE,
+            error: None,
+        })
+    }
+
+    /// Value of `t` at the current frame, without wraparound.
+    fn time(&self) -> u64 {
+        let elapsed = self.frame as u128 * self.rate as u128 / self.sample_rate as u128;
+        self.start.wrapping_add(elapsed as u64)
+    }
+
+    /// Time given to the formula, wrapping around at 32 bits like in C and JavaScript players.
+    fn formula_time(&self, t: u64) -> f64 {
+        if self.mode == OutputMode::Funcbeat {
+            t as f64 / self.rate as f64
+        } else {
+            t as u32 as f64
+        }
+    }
+
+    fn fill_block(&mut self) {
+        for index in 0..BLOCK_SIZE {
+            let t = self.time();
+            self.times[index] = self.formula_time(t);
+            self.right_times[index] = self.formula_time(t.wrapping_add(self.stereo_offset));
+            self.frame += 1;
+        }
+
+        // Failing blocks are silent, and every block is tried again
+        match self.eval_block() {
+            Ok(()) => self.error = None,
+            Err(error) => {
+                if self.error.as_ref() != Some(&error) {
+                    tracing::error!("could not evaluate the formula: {error}");
+                }
+                self.error = Some(error);
+            }
+        }
+        self.index = 0;
+    }
+
+    /// Restart from the start time, at a new output sample rate if given.
+    pub fn reset(&mut self, sample_rate: Option<u32>) {
+        if let Some(sample_rate) = sample_rate {
+            self.sample_rate = sample_rate;
+        }
+        self.frame = 0;
+        self.index = BLOCK_SIZE;
+        self.error = None;
+    }
+
+    pub fn inputs(&self) -> InputValues {
+        self.inputs
+    }
+
+    /// Update the formula inputs, from the next block.
+    pub fn set_inputs(&mut self, inputs: InputValues) {
+        if inputs != self.inputs {
+            self.inputs = inputs;
+            self.inputs_changed = true;
+        }
+    }
+
+    fn eval_block(&mut self) -> Result<(), FormulaError> {
+        if self.inputs_changed {
+            let variables = self.inputs.variables(self.rate);
+            self.formula.set_inputs(&variables)?;
+            if let Some(right) = &mut self.right {
+                right.set_inputs(&variables)?;
+            }
+            self.inputs_changed = false;
+        }
+
+        self.formula.eval_block(&self.times, &mut self.values)?;
+
+        let right = match &mut self.right {
+            Some(right) => right,
+            None if self.stereo_offset == 0 => return Ok(()),
+            None => &mut self.formula,
+        };
+        right.eval_block(&self.right_times, &mut self.right_values)?;
+        for (value, right) in self.values.iter_mut().zip(&self.right_values) {
+            value[1] = right[1];
+        }
+        Ok(())
+    }
+
+    /// The error of the block being played, if it failed.
+    pub fn error(&self) -> Option<&FormulaError> {
+        self.error.as_ref()
+    }
+
+    pub fn next_frame(&mut self) -> [f32; 2] {
+        if self.index == BLOCK_SIZE {
+            self.fill_block();
+        }
+
+        if self.error.is_some() {
+            self.index += 1;
+            return [0.0, 0.0];
+        }
+
+        let [left, right] = self.values[self.index];
+        self.index += 1;
+        [self.mode.to_sample(left), self.mode.to_sample(right)]
+    }
+}
 
-    Ok(())
+pub fn run_bytebeats_synth(
+    output: AudioOutput,
+    source: &BytebeatSource,
+    options: &BytebeatOptions,
+) -> eyre::Result<()> {
+    let mut generator = BytebeatGenerator::new(source, options, output.sample_rate())
+        .map_err(|error| eyre!("invalid formula\n{}", error.highlight()))?;
+    process_stream(output, move || generator.next_frame())
 }
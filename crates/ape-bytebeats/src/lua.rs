@@ -0,0 +1,250 @@
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc,
+};
+
+use rlua::{Context, Function, HookTriggers, Lua, MultiValue, RegistryKey, StdLib, Value};
+
+use crate::{FormulaError, INPUT_NAMES};
+
+/// Globals formulas can use, the others being removed from the environment.
+const ALLOWED_GLOBALS: [&str; 11] = [
+    "_VERSION", "assert", "error", "ipairs", "math", "next", "pairs", "select", "tonumber",
+    "tostring", "type",
+];
+
+/// Instructions Lua code can run to compute one block of samples.
+/// They run in about 2.5 ms, well within the 11.6 ms of a 512 frames block at 44.1 kHz.
+const MAX_INSTRUCTIONS: u32 = 250_000;
+const HOOK_INSTRUCTIONS: u32 = 1_000;
+/// Memory Lua code can allocate, in bytes.
+const MEMORY_LIMIT: usize = 16 * 1024 * 1024;
+
+/// A Lua state with only the libraries formulas and scripts need, and limits on their
+/// instructions and memory.
+pub(crate) struct Sandbox {
+    lua: Lua,
+    /// Hook calls left before the code is stopped.
+    hooks: Arc<AtomicU32>,
+}
+
+impl Sandbox {
+    pub fn new() -> Result<Self, FormulaError> {
+        // Bitwise operators are part of the Lua 5.4 language, they need no library
+        let lua = Lua::new_with(StdLib::BASE | StdLib::MATH);
+        lua.context(|ctx| {
+            let globals = ctx.globals();
+            let names = globals
+                .clone()
+                .pairs::<String, Value>()
+                .map(|pair| pair.map(|(name, _)| name))
+                .collect::<rlua::Result<Vec<_>>>()?;
+            for name in names {
+                if !ALLOWED_GLOBALS.contains(&name.as_str()) {
+                    globals.set(name, Value::Nil)?;
+                }
+            }
+            Ok::<_, rlua::Error>(())
+        })
+        .map_err(lua_error)?;
+        lua.set_memory_limit(Some(MEMORY_LIMIT));
+
+        let hooks = Arc::new(AtomicU32::new(MAX_INSTRUCTIONS / HOOK_INSTRUCTIONS));
+        let budget = hooks.clone();
+        let triggers = HookTriggers {
+            every_nth_instruction: Some(HOOK_INSTRUCTIONS),
+            ..Default::default()
+        };
+        lua.set_hook(triggers, move |_, _| {
+            let left = budget.load(Ordering::Relaxed);
+            if left == 0 {
+                return Err(rlua::Error::RuntimeError(format!(
+                    "ran more than {MAX_INSTRUCTIONS} instructions in one block"
+                )));
+            }
+            budget.store(left - 1, Ordering::Relaxed);
+            Ok(())
+        });
+
+        Ok(Self { lua, hooks })
+    }
+
+    pub fn lua(&self) -> &Lua {
+        &self.lua
+    }
+
+    /// Give the next block its instruction budget.
+    pub fn reset_budget(&self) {
+        self.hooks
+            .store(MAX_INSTRUCTIONS / HOOK_INSTRUCTIONS, Ordering::Relaxed);
+    }
+}
+
+/// Convert Lua errors, explaining the memory limit.
+pub(crate) fn lua_error(error: rlua::Error) -> FormulaError {
+    match error {
+        rlua::Error::MemoryError(_) => FormulaError::new(format!(
+            "used more than {} MiB of memory",
+            MEMORY_LIMIT / 1024 / 1024
+        )),
+        error => FormulaError::from(error),
+    }
+}
+
+/// A bytebeats formula compiled once into a Lua function of `t`.
+pub struct LuaFormula {
+    sandbox: Sandbox,
+    function: RegistryKey,
+    float_time: bool,
+}
+
+impl LuaFormula {
+    /// Compile an expression like `t*(t>>5|t>>8)`, or a chunk ending with a `return`.
+    /// Returning two values, or a table of two values, gives the left and right channels.
+    /// `t` is passed as an integer, unless `float_time` is set.
+    /// The formula is tried once with the given inputs, to report errors like undefined functions
+    /// early.
+    /// Formulas run in a sandbox, with no access to files or the system.
+    pub fn new(
+        formula: &str,
+        float_time: bool,
+        inputs: &[f64; INPUT_NAMES.len()],
+    ) -> Result<Self, FormulaError> {
+        let sandbox = Sandbox::new()?;
+        let function = sandbox.lua().context(|ctx| {
+            // Statement chunks only compile in the second form, report expression errors first
+            let function: Function = match ctx
+                .load(&format!("return function(t) return {formula}\nend"))
+                .eval()
+            {
+                Ok(function) => function,
+                Err(expression_error) => ctx
+                    .load(&format!("return function(t) {formula}\nend"))
+                    .eval()
+                    .map_err(|_| lua_error(expression_error))?,
+            };
+            ctx.create_registry_value(function).map_err(lua_error)
+        })?;
+
+        let formula = Self {
+            sandbox,
+            function,
+            float_time,
+        };
+        formula.set_inputs(inputs)?;
+        formula.eval_block(&[0.0], &mut [[0.0; 2]])?;
+        Ok(formula)
+    }
+
+    /// Set the `INPUT_NAMES` globals.
+    pub fn set_inputs(&self, inputs: &[f64; INPUT_NAMES.len()]) -> Result<(), FormulaError> {
+        let result: rlua::Result<()> = self.sandbox.lua().context(|ctx| {
+            let globals = ctx.globals();
+            for (name, value) in INPUT_NAMES.iter().zip(inputs) {
+                globals.set(*name, number(*value))?;
+            }
+            Ok(())
+        });
+        result.map_err(lua_error)
+    }
+
+    /// Evaluate the formula for each time in `times`.
+    pub fn eval_block(&self, times: &[f64], output: &mut [[f64; 2]]) -> Result<(), FormulaError> {
+        let result: rlua::Result<()> = self.sandbox.lua().context(|ctx| {
+            let function: Function = ctx.registry_value(&self.function)?;
+            self.sandbox.reset_budget();
+            for (t, value) in times.iter().zip(output.iter_mut()) {
+                let t = if self.float_time {
+                    Value::Number(*t)
+                } else {
+                    Value::Integer(*t as i64 as u32 as i64)
+                };
+                let mut values = function.call::<_, MultiValue>(t)?.into_iter();
+                let (left, right) = match values.next().unwrap_or(Value::Nil) {
+                    Value::Table(table) => (table.get(1)?, table.get(2)?),
+                    left => (left, values.next().unwrap_or(Value::Nil)),
+                };
+                // A missing right channel plays the left one
+                let left = to_f64(ctx, "the formula", left)?;
+                *value = match right {
+                    Value::Nil => [left, left],
+                    right => [left, to_f64(ctx, "the formula", right)?],
+                };
+            }
+            Ok(())
+        });
+        result.map_err(lua_error)
+    }
+}
+
+/// Convert a returned value to a sample, numeric strings being coerced like Lua does.
+pub(crate) fn to_f64<'lua>(
+    ctx: Context<'lua>,
+    source: &str,
+    value: Value<'lua>,
+) -> rlua::Result<f64> {
+    let returned = match value {
+        Value::Nil => "nil".to_string(),
+        _ => format!("a {}", value.type_name()),
+    };
+    let number = match value {
+        Value::Integer(v) => Some(v as f64),
+        Value::Number(v) => Some(v),
+        Value::Boolean(v) => Some(v as u32 as f64),
+        Value::String(_) => ctx.coerce_number(value)?,
+        _ => None,
+    };
+    number.ok_or_else(|| {
+        rlua::Error::RuntimeError(format!("{source} returned {returned}, expected a number"))
+    })
+}
+
+/// Integral values are given as integers, for bitwise operators to accept them.
+fn number<'lua>(value: f64) -> Value<'lua> {
+    if value.fract() == 0.0 && value.abs() < i64::MAX as f64 {
+        Value::Integer(value as i64)
+    } else {
+        Value::Number(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn formula(source: &str) -> Result<LuaFormula, FormulaError> {
+        LuaFormula::new(source, false, &[0.0; INPUT_NAMES.len()])
+    }
+
+    #[test]
+    fn bitwise_operators() {
+        let formula = formula("t*(t>>5|t>>8)&255").unwrap();
+        let mut output = [[0.0; 2]];
+        formula.eval_block(&[1000.0], &mut output).unwrap();
+        assert_eq!(
+            output[0][0],
+            ((1000 * (1000 >> 5 | 1000 >> 8)) & 255) as f64
+        );
+    }
+
+    #[test]
+    fn only_math_is_loaded() {
+        assert!(formula("math.floor(t / 2)").is_ok());
+        for source in [
+            "string.rep('a', t)",
+            "#table.pack(t)",
+            "utf8.char(t)",
+            "os.time()",
+        ] {
+            assert!(formula(source).is_err(), "{source}");
+        }
+    }
+
+    #[test]
+    fn endless_formulas_are_stopped() {
+        let error = formula("(function() while true do end end)()")
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("instructions"), "{error}");
+    }
+}
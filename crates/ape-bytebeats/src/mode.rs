@@ -0,0 +1,83 @@
+use std::{fmt::Display, str::FromStr};
+
+/// How the values returned by a formula become samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputMode {
+    /// The low bits of an integer, as an unsigned sample.
+    Unsigned(u32),
+    /// The low bits of an integer, as a two's complement sample.
+    Signed(u32),
+    /// A float from -1 to 1.
+    Floatbeat,
+    /// A float from -1 to 1, with `t` in seconds.
+    Funcbeat,
+}
+
+impl Default for OutputMode {
+    fn default() -> Self {
+        Self::Unsigned(8)
+    }
+}
+
+impl OutputMode {
+    pub fn is_float(&self) -> bool {
+        matches!(self, Self::Floatbeat | Self::Funcbeat)
+    }
+
+    pub fn to_sample(&self, value: f64) -> f32 {
+        match *self {
+            Self::Unsigned(bits) => {
+                let mask = u32::MAX >> (32 - bits);
+                ((value as i64 as u32 & mask) as f64 / mask as f64 * 2.0 - 1.0) as f32
+            }
+            Self::Signed(bits) => {
+                let shift = 32 - bits;
+                let value = (value as i64 as u32) << shift;
+                ((value as i32 >> shift) as f64 / (1u64 << (bits - 1)) as f64) as f32
+            }
+            Self::Floatbeat | Self::Funcbeat if value.is_nan() => 0.0,
+            Self::Floatbeat | Self::Funcbeat => value.clamp(-1.0, 1.0) as f32,
+        }
+    }
+}
+
+impl Display for OutputMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unsigned(8) => write!(f, "bytebeat"),
+            Self::Signed(8) => write!(f, "signed"),
+            Self::Unsigned(bits) => write!(f, "u{bits}"),
+            Self::Signed(bits) => write!(f, "s{bits}"),
+            Self::Floatbeat => write!(f, "floatbeat"),
+            Self::Funcbeat => write!(f, "funcbeat"),
+        }
+    }
+}
+
+impl FromStr for OutputMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_lowercase();
+        let bits = |bits: &str| match bits.parse() {
+            Ok(bits @ 1..=32) => Ok(bits),
+            _ => Err(format!("invalid bit depth '{bits}', expected 1 to 32")),
+        };
+
+        match s.as_str() {
+            "bytebeat" | "unsigned" => Ok(Self::Unsigned(8)),
+            "signed" => Ok(Self::Signed(8)),
+            "floatbeat" => Ok(Self::Floatbeat),
+            "funcbeat" => Ok(Self::Funcbeat),
+            _ => {
+                if let Some(depth) = s.strip_prefix('u') {
+                    Ok(Self::Unsigned(bits(depth)?))
+                } else if let Some(depth) = s.strip_prefix('s') {
+                    Ok(Self::Signed(bits(depth)?))
+                } else {
+                    Err(format!("unknown output mode '{s}'"))
+                }
+            }
+        }
+    }
+}
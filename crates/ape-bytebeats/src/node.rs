@@ -0,0 +1,84 @@
+use fundsp::hacker::{An, AudioNode, Frame, Tag, U0, U2};
+
+use crate::{BytebeatGenerator, BytebeatOptions, BytebeatSource, FormulaError, INPUT_NAMES};
+
+/// Sample rate of nodes until they are reset, like other fundsp nodes.
+const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+
+/// A bytebeat generator as a fundsp node, with no inputs and two outputs.
+pub struct BytebeatNode {
+    generator: BytebeatGenerator,
+    /// Tag of the `a` input, the other inputs following in the order of `INPUT_NAMES`.
+    input_tags: Option<Tag>,
+}
+
+impl BytebeatNode {
+    pub fn new(generator: BytebeatGenerator) -> Self {
+        Self {
+            generator,
+            input_tags: None,
+        }
+    }
+
+    /// Set the formula inputs from tags, starting at `first` for `a`, up to `my`.
+    pub fn with_input_tags(mut self, first: Tag) -> Self {
+        self.input_tags = Some(first);
+        self
+    }
+
+    /// Index in `INPUT_NAMES` of the input set by `tag`, `sr` being fixed by the formula rate.
+    fn input_index(&self, tag: Tag) -> Option<usize> {
+        let index = tag.checked_sub(self.input_tags?)?;
+        (index < INPUT_NAMES.len() as Tag - 1).then_some(index as usize)
+    }
+}
+
+impl AudioNode for BytebeatNode {
+    const ID: u64 = 0x6279_7465_6265_6174;
+    type Sample = f64;
+    type Inputs = U0;
+    type Outputs = U2;
+
+    fn reset(&mut self, sample_rate: Option<f64>) {
+        self.generator
+            .reset(sample_rate.map(|sample_rate| sample_rate.round() as u32));
+    }
+
+    fn tick(&mut self, _input: &Frame<f64, U0>) -> Frame<f64, U2> {
+        let [left, right] = self.generator.next_frame();
+        [left as f64, right as f64].into()
+    }
+
+    fn set(&mut self, parameter: Tag, value: f64) {
+        if let Some(index) = self.input_index(parameter) {
+            let mut inputs = self.generator.inputs();
+            match index {
+                0..=7 => inputs.sliders[index] = value,
+                8 => inputs.freq = value,
+                9 => inputs.velocity = value,
+                _ => inputs.mouse[index - 10] = value,
+            }
+            self.generator.set_inputs(inputs);
+        }
+    }
+
+    fn get(&self, parameter: Tag) -> Option<f64> {
+        let inputs = self.generator.inputs();
+        let index = self.input_index(parameter)?;
+        Some(match index {
+            0..=7 => inputs.sliders[index],
+            8 => inputs.freq,
+            9 => inputs.velocity,
+            _ => inputs.mouse[index - 10],
+        })
+    }
+}
+
+/// A bytebeat node to use in fundsp graphs, e.g. `bytebeat(..)? >> (lowpass_hz(2000.0, 1.0) | lowpass_hz(2000.0, 1.0))`.
+pub fn bytebeat(
+    source: &BytebeatSource,
+    options: &BytebeatOptions,
+) -> Result<An<BytebeatNode>, FormulaError> {
+    let generator = BytebeatGenerator::new(source, options, DEFAULT_SAMPLE_RATE)?;
+    Ok(An(BytebeatNode::new(generator)))
+}
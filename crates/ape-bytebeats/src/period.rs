@@ -0,0 +1,101 @@
+use std::path::Path;
+
+use ape_core::{
+    color_eyre::eyre::{self, eyre},
+    export::export_frames_to_wav,
+    hound,
+};
+
+use crate::{BytebeatGenerator, BytebeatOptions, BytebeatSource, FormulaError};
+
+/// Longest period searched, in `t`, keeping the searched values and their prefix table around
+/// 200 MiB.
+pub const MAX_PERIOD: u64 = 1 << 23;
+
+/// Smallest period of a formula output, in `t` from the start time, if it repeats at least twice
+/// in the first `2 * max_period` values.
+/// Outputs are compared exactly, after the output mode and the stereo offset. `max_period` is
+/// capped to [`MAX_PERIOD`].
+pub fn detect_period(
+    source: &BytebeatSource,
+    options: &BytebeatOptions,
+    max_period: u64,
+) -> Result<Option<u64>, FormulaError> {
+    // Played at its own rate, the generator gives one frame per `t`
+    let mut generator = BytebeatGenerator::new(source, options, options.rate)?;
+    let max_period = max_period.min(MAX_PERIOD);
+    let len = max_period as usize * 2;
+    let mut values = Vec::with_capacity(len);
+    for _ in 0..len {
+        let [left, right] = generator.next_frame();
+        if let Some(error) = generator.error() {
+            return Err(error.clone());
+        }
+        values.push([left.to_bits(), right.to_bits()]);
+    }
+
+    let period = smallest_period(&values) as u64;
+    // Without any value, no period is found
+    Ok((1..=max_period).contains(&period).then_some(period))
+}
+
+/// Smallest `p` with `values[i] == values[i + p]` for all `i`, using the KMP prefix function.
+fn smallest_period<T: PartialEq>(values: &[T]) -> usize {
+    // Length of the longest proper prefix of `values[..=i]` that is also a suffix of it
+    // Lengths are stored as `u32` to halve the table, searches staying below `2 * MAX_PERIOD`
+    let mut prefix = vec![0_u32; values.len()];
+    for i in 1..values.len() {
+        let mut k = prefix[i - 1] as usize;
+        while k > 0 && values[i] != values[k] {
+            k = prefix[k - 1] as usize;
+        }
+        if values[i] == values[k] {
+            k += 1;
+        }
+        prefix[i] = k as u32;
+    }
+
+    values.len() - prefix.last().copied().unwrap_or(0) as usize
+}
+
+/// Render `periods` periods of a formula to a seamless loop, returning the period.
+/// The file is written at the formula rate by default, other rates needing a period lasting a
+/// whole number of samples.
+pub fn export_loop(
+    path: &Path,
+    source: &BytebeatSource,
+    options: &BytebeatOptions,
+    periods: u64,
+    max_period: u64,
+    sample_rate: Option<u32>,
+) -> eyre::Result<u64> {
+    let period = detect_period(source, options, max_period)
+        .map_err(|error| eyre!("invalid formula\n{}", error.highlight()))?
+        .ok_or_else(|| eyre!("the formula does not repeat within {max_period} t"))?;
+
+    let sample_rate = sample_rate.unwrap_or(options.rate);
+    let length = period
+        .checked_mul(periods)
+        .ok_or_else(|| eyre!("{periods} periods of {period} t are too long to export"))?
+        as u128
+        * sample_rate as u128;
+    if length % options.rate as u128 != 0 {
+        return Err(eyre!(
+            "{periods} period(s) of {period} t do not last a whole number of samples at \
+             {sample_rate} Hz, export at the formula rate instead"
+        ));
+    }
+
+    let spec = hound::WavSpec {
+        bits_per_sample: 16,
+        channels: 2,
+        sample_format: hound::SampleFormat::Int,
+        sample_rate,
+    };
+    let mut generator = BytebeatGenerator::new(source, options, sample_rate)
+        .map_err(|error| eyre!("invalid formula\n{}", error.highlight()))?;
+    let frames = (length / options.rate as u128) as usize;
+    export_frames_to_wav(path, spec, frames, || generator.next_frame())?;
+
+    Ok(period)
+}
@@ -0,0 +1,161 @@
+use ape_core::{
+    color_eyre::eyre::{self, eyre},
+    process_stream, tracing, AudioOutput,
+};
+
+use crate::{BytebeatGenerator, FormulaError, Preset};
+
+/// Plays presets one after the other in a loop, crossfading between them.
+pub struct Playlist {
+    generators: Vec<BytebeatGenerator>,
+    current: usize,
+    /// Frames played of the current preset.
+    position: usize,
+    /// Frames between the starts of two presets, each one fading out for `crossfade` frames after.
+    duration: usize,
+    crossfade: usize,
+}
+
+impl Playlist {
+    /// `duration` and `crossfade` are in seconds, the crossfade being at most the duration.
+    pub fn new(
+        presets: &[Preset],
+        duration: f64,
+        crossfade: f64,
+        sample_rate: u32,
+    ) -> Result<Self, FormulaError> {
+        if presets.is_empty() {
+            return Err(FormulaError::new("the playlist has no presets"));
+        }
+
+        let generators = presets
+            .iter()
+            .map(|preset| {
+                BytebeatGenerator::new(&preset.source, &preset.options, sample_rate).map_err(
+                    |error| {
+                        FormulaError::new(format!(
+                            "preset '{}': {}",
+                            preset.name,
+                            error.highlight()
+                        ))
+                    },
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let duration = ((duration * sample_rate as f64) as usize).max(1);
+        Ok(Self {
+            generators,
+            current: 0,
+            position: 0,
+            duration,
+            crossfade: ((crossfade * sample_rate as f64) as usize).min(duration),
+        })
+    }
+
+    /// Index of the preset playing, the next one fading in at the end.
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    pub fn next_frame(&mut self) -> [f32; 2] {
+        let next = (self.current + 1) % self.generators.len();
+        let fade_start = self.duration;
+        if self.position == fade_start && next != self.current {
+            self.generators[next].reset(None);
+        }
+
+        let mut frame = self.generators[self.current].next_frame();
+        if self.position >= fade_start && next != self.current {
+            let fade = (self.position - fade_start) as f32 / self.crossfade as f32;
+            let incoming = self.generators[next].next_frame();
+            for (value, incoming) in frame.iter_mut().zip(incoming) {
+                *value = *value * (1.0 - fade) + incoming * fade;
+            }
+        }
+
+        self.position += 1;
+        if self.position == self.duration + self.crossfade {
+            self.current = next;
+            self.position = self.crossfade;
+            // Without a crossfade, the preset did not start yet
+            if self.crossfade == 0 || self.generators.len() == 1 {
+                self.generators[next].reset(None);
+                self.position = 0;
+            }
+        }
+
+        frame
+    }
+}
+
+pub fn run_playlist(
+    output: AudioOutput,
+    presets: &[Preset],
+    duration: f64,
+    crossfade: f64,
+) -> eyre::Result<()> {
+    let mut playlist = Playlist::new(presets, duration, crossfade, output.sample_rate())
+        .map_err(|error| eyre!("invalid playlist\n{error}"))?;
+    let names: Vec<_> = presets.iter().map(|preset| preset.name.clone()).collect();
+
+    let mut playing = None;
+    process_stream(output, move || {
+        if playing != Some(playlist.current()) {
+            playing = Some(playlist.current());
+            tracing::info!("playing '{}'", names[playlist.current()]);
+        }
+        playlist.next_frame()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PresetLibrary;
+
+    fn presets(formulas: &[&str]) -> Vec<Preset> {
+        let library: PresetLibrary = formulas
+            .iter()
+            .enumerate()
+            .map(|(index, formula)| format!("preset {index}\ndialect c\nformula {formula}\n"))
+            .collect::<String>()
+            .parse()
+            .unwrap();
+        library.presets
+    }
+
+    /// Constant left value of a preset.
+    fn value(preset: &Preset) -> f32 {
+        BytebeatGenerator::new(&preset.source, &preset.options, 8_000)
+            .unwrap()
+            .next_frame()[0]
+    }
+
+    #[test]
+    fn crossfade_gains() {
+        let presets = presets(&["64", "192"]);
+        let (a, b) = (value(&presets[0]), value(&presets[1]));
+        // 8 frames per preset, fading out for 4 frames
+        let mut playlist = Playlist::new(&presets, 0.001, 0.0005, 8_000).unwrap();
+        let frames: Vec<f32> = (0..24).map(|_| playlist.next_frame()[0]).collect();
+
+        let mix = |fade: f32| a * (1.0 - fade) + b * fade;
+        let mut expected = vec![a; 8];
+        expected.extend([0.0, 0.25, 0.5, 0.75].map(mix));
+        expected.extend([b; 4]);
+        expected.extend([1.0, 0.75, 0.5, 0.25].map(mix));
+        expected.extend([a; 4]);
+        assert_eq!(frames, expected);
+    }
+
+    #[test]
+    fn without_crossfade() {
+        let presets = presets(&["64", "192"]);
+        let (a, b) = (value(&presets[0]), value(&presets[1]));
+        let mut playlist = Playlist::new(&presets, 0.0005, 0.0, 8_000).unwrap();
+        let frames: Vec<f32> = (0..12).map(|_| playlist.next_frame()[0]).collect();
+        assert_eq!(frames, [a, a, a, a, b, b, b, b, a, a, a, a]);
+        assert!(Playlist::new(&[], 1.0, 0.0, 8_000).is_err());
+    }
+}
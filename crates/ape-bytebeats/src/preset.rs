@@ -0,0 +1,264 @@
+use std::{fmt::Display, path::Path, str::FromStr};
+
+use ape_core::color_eyre::eyre::{self, eyre};
+
+use crate::{parse_rate, BytebeatOptions, BytebeatSource};
+
+/// A named formula with its options.
+#[derive(Debug, Clone)]
+pub struct Preset {
+    pub name: String,
+    pub author: Option<String>,
+    pub source: BytebeatSource,
+    pub options: BytebeatOptions,
+    pub tags: Vec<String>,
+}
+
+impl Preset {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.into(),
+            author: None,
+            source: BytebeatSource::Single(String::new()),
+            options: BytebeatOptions::default(),
+            tags: vec![],
+        }
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t.eq_ignore_ascii_case(tag))
+    }
+}
+
+/// Writes the preset in the library format.
+impl Display for Preset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "preset {}", self.name)?;
+        if let Some(author) = &self.author {
+            writeln!(f, "author {author}")?;
+        }
+        if !self.tags.is_empty() {
+            writeln!(f, "tags {}", self.tags.join(", "))?;
+        }
+
+        let options = &self.options;
+        writeln!(f, "dialect {}", options.dialect)?;
+        writeln!(f, "rate {}", options.rate)?;
+        writeln!(f, "mode {}", options.mode)?;
+        if options.start != 0 {
+            writeln!(f, "start {}", options.start)?;
+        }
+        if options.stereo_offset != 0 {
+            writeln!(f, "stereo-offset {}", options.stereo_offset)?;
+        }
+
+        match &self.source {
+            BytebeatSource::Single(formula) => writeln!(f, "formula {formula}"),
+            BytebeatSource::Split(left, right) => {
+                writeln!(f, "left {left}")?;
+                writeln!(f, "right {right}")
+            }
+        }
+    }
+}
+
+/// Presets read from a library file.
+///
+/// ```text
+/// # Comments start with '#'
+/// preset crowd
+/// author Kragen
+/// tags classic, c
+/// dialect c
+/// rate 8k
+/// mode bytebeat
+/// formula ((t<<1)^((t<<1)+(t>>7)&t>>12))|t>>(4-(1^7&(t>>19)))|t>>7
+/// ```
+///
+/// `formula` can be replaced by `left` and `right` formulas, and `start` and `stereo-offset`
+/// set the other options.
+#[derive(Debug, Clone, Default)]
+pub struct PresetLibrary {
+    pub presets: Vec<Preset>,
+}
+
+impl PresetLibrary {
+    pub fn from_file(path: &Path) -> eyre::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        contents.parse()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Preset> {
+        self.presets.iter().find(|preset| preset.name == name)
+    }
+}
+
+fn parse_value<T: FromStr>(keyword: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid {keyword} '{value}'"))
+}
+
+fn parse_line(library: &mut PresetLibrary, line: &str) -> Result<(), String> {
+    let (keyword, value) = match line.split_once(char::is_whitespace) {
+        Some((keyword, value)) => (keyword, value.trim()),
+        None => (line, ""),
+    };
+    if keyword.is_empty() {
+        return Ok(());
+    }
+    if value.is_empty() {
+        return Err(format!("missing {keyword} value"));
+    }
+
+    if keyword == "preset" {
+        library.presets.push(Preset::new(value));
+        return Ok(());
+    }
+
+    let preset = library
+        .presets
+        .last_mut()
+        .ok_or_else(|| format!("'{keyword}' outside of a preset"))?;
+    let options = &mut preset.options;
+    match keyword {
+        "author" => preset.author = Some(value.into()),
+        "tags" => {
+            preset.tags = value
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|tag| !tag.is_empty())
+                .map(Into::into)
+                .collect()
+        }
+        "dialect" => options.dialect = value.parse()?,
+        "mode" => options.mode = value.parse()?,
+        "rate" => options.rate = parse_rate(value)?,
+        "start" => options.start = parse_value(keyword, value)?,
+        "stereo-offset" => options.stereo_offset = parse_value(keyword, value)?,
+        "formula" => preset.source = BytebeatSource::Single(value.into()),
+        "left" | "right" => {
+            let (mut left, mut right) = match &preset.source {
+                BytebeatSource::Split(left, right) => (left.clone(), right.clone()),
+                BytebeatSource::Single(_) => (String::new(), String::new()),
+            };
+            if keyword == "left" {
+                left = value.into();
+            } else {
+                right = value.into();
+            }
+            preset.source = BytebeatSource::Split(left, right);
+        }
+        _ => return Err(format!("unknown keyword '{keyword}'")),
+    }
+
+    Ok(())
+}
+
+impl FromStr for PresetLibrary {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut library = PresetLibrary::default();
+        // Only whole lines are comments, '#' being the Lua length operator
+        for (index, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.starts_with('#') {
+                continue;
+            }
+            parse_line(&mut library, line).map_err(|e| eyre!("line {}: {e}", index + 1))?;
+        }
+
+        for preset in &library.presets {
+            let complete = match &preset.source {
+                BytebeatSource::Single(formula) => !formula.is_empty(),
+                BytebeatSource::Split(left, right) => !left.is_empty() && !right.is_empty(),
+            };
+            if !complete {
+                return Err(eyre!("preset '{}' has no formula", preset.name));
+            }
+        }
+
+        Ok(library)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Dialect, OutputMode};
+
+    const LIBRARY: &str = "
+# A comment
+preset crowd
+author Kragen
+tags classic, c
+dialect c
+rate 11k
+mode signed
+formula ((t<<1)^((t<<1)+(t>>7)&t>>12))|t>>(4-(1^7&(t>>19)))|t>>7
+
+preset split
+start 100
+stereo-offset 8
+left t*(t>>5|t>>8)
+right #'abc' * t
+";
+
+    #[test]
+    fn parse() {
+        let library: PresetLibrary = LIBRARY.parse().unwrap();
+        assert_eq!(library.presets.len(), 2);
+
+        let crowd = library.get("crowd").unwrap();
+        assert_eq!(crowd.author.as_deref(), Some("Kragen"));
+        assert!(crowd.has_tag("Classic") && crowd.has_tag("c"));
+        assert_eq!(crowd.options.dialect, Dialect::C);
+        assert_eq!(crowd.options.rate, 11_025);
+        assert_eq!(crowd.options.mode, OutputMode::Signed(8));
+
+        let split = library.get("split").unwrap();
+        assert_eq!(split.options.dialect, Dialect::Lua);
+        assert_eq!(split.options.start, 100);
+        assert_eq!(split.options.stereo_offset, 8);
+        assert_eq!(
+            split.source,
+            BytebeatSource::Split("t*(t>>5|t>>8)".into(), "#'abc' * t".into())
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let library: PresetLibrary = LIBRARY.parse().unwrap();
+        let written: String = library.presets.iter().map(ToString::to_string).collect();
+        let read: PresetLibrary = written.parse().unwrap();
+
+        for (preset, read) in library.presets.iter().zip(&read.presets) {
+            assert_eq!(read.name, preset.name);
+            assert_eq!(read.author, preset.author);
+            assert_eq!(read.tags, preset.tags);
+            assert_eq!(read.source, preset.source);
+            assert_eq!(read.options.dialect, preset.options.dialect);
+            assert_eq!(read.options.rate, preset.options.rate);
+            assert_eq!(read.options.mode, preset.options.mode);
+            assert_eq!(read.options.start, preset.options.start);
+            assert_eq!(read.options.stereo_offset, preset.options.stereo_offset);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let error = |library: &str| library.parse::<PresetLibrary>().unwrap_err().to_string();
+        assert_eq!(error("formula t"), "line 1: 'formula' outside of a preset");
+        assert_eq!(
+            error("preset a\nvolume 3"),
+            "line 2: unknown keyword 'volume'"
+        );
+        assert_eq!(error("preset a\nrate"), "line 2: missing rate value");
+        assert_eq!(
+            error("preset a\nstart soon"),
+            "line 2: invalid start 'soon'"
+        );
+        assert_eq!(error("preset a\nleft t"), "preset 'a' has no formula");
+        assert!(error("preset a\nmode loud").starts_with("line 2: "));
+    }
+}
@@ -0,0 +1,151 @@
+use ape_core::{
+    color_eyre::eyre::{self, eyre},
+    process_stream, tracing, AudioOutput,
+};
+use rlua::{Function, MultiValue, RegistryKey, Table, Value};
+
+use crate::{
+    lua::{lua_error, to_f64, Sandbox},
+    FormulaError, OutputMode, BLOCK_SIZE,
+};
+
+/// Function of a script computing its samples.
+enum Callback {
+    /// `process(frames)`, returning a table of frames.
+    Process(RegistryKey),
+    /// `tick()`, returning one frame.
+    Tick(RegistryKey),
+}
+
+/// A Lua synth keeping its state between calls.
+///
+/// The script can define `init(sample_rate)`, called once, and must define either
+/// `process(frames)`, returning a table of `frames` values, or `tick()`, returning one value.
+/// A value is a mono sample, or the left and right samples (as a `{left, right}` table in
+/// `process`), from -1 to 1.
+pub struct LuaScript {
+    sandbox: Sandbox,
+    callback: Callback,
+}
+
+impl LuaScript {
+    pub fn new(source: &str, sample_rate: u32) -> Result<Self, FormulaError> {
+        let sandbox = Sandbox::new()?;
+        sandbox.reset_budget();
+        let callback = sandbox
+            .lua()
+            .context(|ctx| {
+                ctx.load(source).exec()?;
+
+                let globals = ctx.globals();
+                if let Some(init) = globals.get::<_, Option<Function>>("init")? {
+                    init.call::<_, ()>(sample_rate)?;
+                }
+
+                let callback =
+                    if let Some(process) = globals.get::<_, Option<Function>>("process")? {
+                        Callback::Process(ctx.create_registry_value(process)?)
+                    } else if let Some(tick) = globals.get::<_, Option<Function>>("tick")? {
+                        Callback::Tick(ctx.create_registry_value(tick)?)
+                    } else {
+                        return Ok(None);
+                    };
+                Ok(Some(callback))
+            })
+            .map_err(lua_error)?
+            .ok_or_else(|| FormulaError::new("the script defines no process or tick function"))?;
+
+        Ok(Self { sandbox, callback })
+    }
+
+    /// Compute the next `output.len()` frames.
+    pub fn eval_block(&self, output: &mut [[f64; 2]]) -> Result<(), FormulaError> {
+        let result: rlua::Result<()> = self.sandbox.lua().context(|ctx| match &self.callback {
+            Callback::Process(process) => {
+                let process: Function = ctx.registry_value(process)?;
+                self.sandbox.reset_budget();
+                let frames: Table = process.call(output.len())?;
+                for (index, value) in output.iter_mut().enumerate() {
+                    *value = match frames.get(index + 1)? {
+                        Value::Table(frame) => [
+                            to_f64(ctx, "the script", frame.get(1)?)?,
+                            to_f64(ctx, "the script", frame.get(2)?)?,
+                        ],
+                        value => [to_f64(ctx, "the script", value)?; 2],
+                    };
+                }
+                Ok(())
+            }
+            Callback::Tick(tick) => {
+                let tick: Function = ctx.registry_value(tick)?;
+                self.sandbox.reset_budget();
+                for value in output.iter_mut() {
+                    let mut values = tick.call::<_, MultiValue>(())?.into_iter();
+                    let left = to_f64(ctx, "the script", values.next().unwrap_or(Value::Nil))?;
+                    *value = match values.next() {
+                        Some(right) => [left, to_f64(ctx, "the script", right)?],
+                        None => [left, left],
+                    };
+                }
+                Ok(())
+            }
+        });
+        result.map_err(lua_error)
+    }
+}
+
+/// Plays a script block by block.
+pub struct ScriptGenerator {
+    script: LuaScript,
+    values: [[f64; 2]; BLOCK_SIZE],
+    index: usize,
+    /// Set while the current block failed and plays silence.
+    error: Option<FormulaError>,
+}
+
+impl ScriptGenerator {
+    pub fn new(source: &str, sample_rate: u32) -> Result<Self, FormulaError> {
+        Ok(Self {
+            script: LuaScript::new(source, sample_rate)?,
+            values: [[0.0; 2]; BLOCK_SIZE],
+            index: BLOCK_SIZE,
+            error: None,
+        })
+    }
+
+    fn fill_block(&mut self) {
+        // Failing blocks are silent, and every block is tried again
+        match self.script.eval_block(&mut self.values) {
+            Ok(()) => self.error = None,
+            Err(error) => {
+                if self.error.as_ref() != Some(&error) {
+                    tracing::error!("could not run the script: {error}");
+                }
+                self.error = Some(error);
+            }
+        }
+        self.index = 0;
+    }
+
+    pub fn next_frame(&mut self) -> [f32; 2] {
+        if self.index == BLOCK_SIZE {
+            self.fill_block();
+        }
+
+        if self.error.is_some() {
+            self.index += 1;
+            return [0.0, 0.0];
+        }
+
+        let [left, right] = self.values[self.index];
+        self.index += 1;
+        let mode = OutputMode::Floatbeat;
+        [mode.to_sample(left), mode.to_sample(right)]
+    }
+}
+
+pub fn run_script_synth(output: AudioOutput, source: &str) -> eyre::Result<()> {
+    let mut generator = ScriptGenerator::new(source, output.sample_rate())
+        .map_err(|error| eyre!("invalid script\n{error}"))?;
+    process_stream(output, move || generator.next_frame())
+}
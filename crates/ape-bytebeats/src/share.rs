@@ -0,0 +1,169 @@
+use std::io::{Read, Write};
+
+use ape_core::color_eyre::eyre::{self, eyre};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use serde::{Deserialize, Serialize};
+
+use crate::{BytebeatOptions, BytebeatSource, Dialect, OutputMode};
+
+/// Web player whose links are created.
+const PLAYER_URL: &str = "https://dollchan.net/bytebeat/";
+
+/// Largest decompressed link contents, in bytes.
+const MAX_LINK_SIZE: u64 = 1 << 20;
+
+/// A formula as stored in links, deflated and encoded in base64 after `#v3b64`.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SharedFormula {
+    code: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mode: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sample_rate: Option<serde_json::Number>,
+}
+
+fn mode_name(mode: OutputMode) -> eyre::Result<&'static str> {
+    Ok(match mode {
+        OutputMode::Unsigned(8) => "Bytebeat",
+        OutputMode::Signed(8) => "Signed Bytebeat",
+        OutputMode::Floatbeat => "Floatbeat",
+        OutputMode::Funcbeat => "Funcbeat",
+        mode => return Err(eyre!("web players have no {mode} mode")),
+    })
+}
+
+fn parse_mode_name(name: &str) -> eyre::Result<OutputMode> {
+    Ok(match name {
+        "Bytebeat" => OutputMode::Unsigned(8),
+        "Signed Bytebeat" => OutputMode::Signed(8),
+        "Floatbeat" => OutputMode::Floatbeat,
+        "Funcbeat" => OutputMode::Funcbeat,
+        name => return Err(eyre!("unknown mode '{name}'")),
+    })
+}
+
+/// Read a link from a web bytebeat player, or only its `#` fragment.
+/// Its JavaScript code is read as a C formula, so floatbeat and funcbeat links are rejected.
+pub fn decode_share_url(url: &str) -> eyre::Result<(BytebeatSource, BytebeatOptions)> {
+    let fragment = url.rsplit_once('#').map_or(url, |(_, fragment)| fragment);
+    let encoded = fragment
+        .strip_prefix("v3b64")
+        .or_else(|| fragment.strip_prefix("b64"))
+        .ok_or_else(|| eyre!("unsupported link, expected a '#v3b64' fragment"))?;
+
+    let encoded = encoded.trim();
+    let compressed = base64::decode(encoded)
+        .or_else(|_| base64::decode_config(encoded, base64::URL_SAFE))
+        .map_err(|error| eyre!("invalid link encoding: {error}"))?;
+    let mut json = String::new();
+    DeflateDecoder::new(compressed.as_slice())
+        .take(MAX_LINK_SIZE + 1)
+        .read_to_string(&mut json)
+        .map_err(|error| eyre!("invalid link compression: {error}"))?;
+    if json.len() as u64 > MAX_LINK_SIZE {
+        return Err(eyre!(
+            "the link contents are larger than {} KiB",
+            MAX_LINK_SIZE / 1024
+        ));
+    }
+    let shared: SharedFormula =
+        serde_json::from_str(&json).map_err(|error| eyre!("invalid link contents: {error}"))?;
+
+    let mut options = BytebeatOptions {
+        dialect: Dialect::C,
+        ..Default::default()
+    };
+    if let Some(mode) = &shared.mode {
+        options.mode = parse_mode_name(mode)?;
+    }
+    if options.mode.is_float() {
+        return Err(eyre!(
+            "{} links run JavaScript with floats, only bytebeat and signed bytebeat links can be \
+             read as C formulas",
+            options.mode
+        ));
+    }
+    if let Some(rate) = shared.sample_rate.and_then(|rate| rate.as_f64()) {
+        if rate < 1.0 {
+            return Err(eyre!("invalid sample rate {rate}"));
+        }
+        options.rate = rate.round() as u32;
+    }
+
+    Ok((BytebeatSource::Single(shared.code), options))
+}
+
+/// Create a web player link playing a C formula.
+/// Split formulas are joined into a `[left, right]` formula.
+pub fn encode_share_url(
+    source: &BytebeatSource,
+    options: &BytebeatOptions,
+) -> eyre::Result<String> {
+    if options.dialect != Dialect::C {
+        return Err(eyre!(
+            "only C formulas can be shared, web players run JavaScript"
+        ));
+    }
+    if options.start != 0 || options.stereo_offset != 0 {
+        return Err(eyre!(
+            "web player links cannot keep a start or stereo offset"
+        ));
+    }
+
+    let code = match source {
+        BytebeatSource::Single(formula) => formula.clone(),
+        BytebeatSource::Split(left, right) => format!("[{left}, {right}]"),
+    };
+    let shared = SharedFormula {
+        code,
+        mode: Some(mode_name(options.mode)?.into()),
+        sample_rate: Some(options.rate.into()),
+    };
+
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(serde_json::to_string(&shared)?.as_bytes())?;
+    let compressed = encoder.finish()?;
+
+    Ok(format!("{PLAYER_URL}#v3b64{}", base64::encode(compressed)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(json: &str) -> String {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(json.as_bytes()).unwrap();
+        format!("#v3b64{}", base64::encode(encoder.finish().unwrap()))
+    }
+
+    #[test]
+    fn round_trip() {
+        let options = BytebeatOptions {
+            dialect: Dialect::C,
+            mode: OutputMode::Signed(8),
+            rate: 11_025,
+            ..Default::default()
+        };
+        let source = BytebeatSource::Split("t".into(), "t>>1".into());
+        let url = encode_share_url(&source, &options).unwrap();
+        let (source, decoded) = decode_share_url(&url).unwrap();
+        assert!(matches!(source, BytebeatSource::Single(code) if code == "[t, t>>1]"));
+        assert_eq!(decoded.mode, options.mode);
+        assert_eq!(decoded.rate, options.rate);
+    }
+
+    #[test]
+    fn float_links_are_rejected() {
+        let url = link(r#"{"code":"Math.sin(t)","mode":"Floatbeat"}"#);
+        let error = decode_share_url(&url).unwrap_err().to_string();
+        assert!(error.contains("floatbeat links"), "{error}");
+    }
+
+    #[test]
+    fn large_links_are_rejected() {
+        let url = link(&format!(r#"{{"code":"{}"}}"#, "t".repeat(2 << 20)));
+        assert!(decode_share_url(&url).is_err());
+    }
+}
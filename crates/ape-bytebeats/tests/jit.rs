@@ -0,0 +1,83 @@
+#![cfg(feature = "jit")]
+
+use ape_bytebeats::{ExprFormula, JitFormula};
+
+const CLASSICS: &[&str] = &[
+    "t",
+    "t*(t>>5|t>>8)",
+    "t*(t>>11&t>>8&123&t>>3)",
+    "t*((t>>12|t>>8)&63&t>>4)",
+    "(t*(t>>5|t>>8))>>(t>>16)",
+    "((-t&4095)*(255&t*(t&t>>13))>>12)+(127&t*(234&t>>8&t>>3)>>(3&t>>14))",
+    "t*(t>>((t>>9|t>>8))&63&t>>4)",
+    "(t>>6|t|t>>(t>>16))*10+((t>>11)&7)",
+    "(t|(t>>9|t>>7))*t&(t>>11|t>>9)",
+    "t*5&(t>>7)|t*3&(t*4>>10)",
+    "(t>>7|t|t>>6)*10+4*(t&t>>13|t>>6)",
+    "((t&4096)?((t*(t^t%255)|(t>>4))>>1):(t>>3)|((t&8192)?t<<2:t))",
+    "t*(0xCA98>>(t>>9&14)&15)|t>>8",
+    "(t*9&t>>4|t*5&t>>7|t*3&t/1024)-1",
+    "t>>4|t&((t>>5)/(t>>7-(t>>15)&-t>>7-(t>>15)))",
+    "t%(t>>10&t)",
+    "t/(t>>9&7)",
+    "-t%(t>>8&3)-1",
+    "t<<(t>>10)",
+    "t>>>(t>>12)^t>>(t>>12)",
+    "t*t/(t>>13^t>>8)",
+    "!(t&512)*t*3|~t>>6",
+    "(t>100&&t<50000)||t%3==0?t*3:t!=7&&t>=9<=1",
+    "(t<<16)/-1+(t<<31)%-1+(-2147483647-1)/(t&1?-1:1)",
+    "[t*(t>>5|t>>8), t*(t>>4|t>>9)]",
+    "[(t>>7|t|t>>6)*10, t>>(t>>12&7)]",
+];
+
+/// Pseudo random times, including the ones wrapping to negative integers.
+fn times() -> impl Iterator<Item = u32> {
+    let edges = [
+        0,
+        1,
+        255,
+        65_535,
+        1 << 24,
+        i32::MAX as u32,
+        1 << 31,
+        u32::MAX,
+    ];
+    let mut state = 0x2545_f491_u32;
+    let random = (0..20_000).map(move |_| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    });
+    edges.into_iter().chain(0..20_000).chain(random)
+}
+
+#[test]
+fn jit_matches_interpreter() {
+    for source in CLASSICS {
+        let mut interpreted = ExprFormula::new(source).unwrap();
+        let mut compiled = JitFormula::new(&interpreted).unwrap();
+
+        for t in times() {
+            assert_eq!(
+                compiled.eval(t),
+                interpreted.eval(t),
+                "formula '{source}' at t = {t}"
+            );
+        }
+    }
+}
+
+#[test]
+fn jit_matches_interpreter_in_blocks() {
+    let mut interpreted = ExprFormula::new(CLASSICS[CLASSICS.len() - 1]).unwrap();
+    let mut compiled = JitFormula::new(&interpreted).unwrap();
+    let times: Vec<f64> = times().map(|t| t as f64).collect();
+    let mut expected = vec![[0.0; 2]; times.len()];
+    let mut actual = vec![[0.0; 2]; times.len()];
+
+    interpreted.eval_block(&times, &mut expected);
+    compiled.eval_block(&times, &mut actual);
+    assert_eq!(actual, expected);
+}
@@ -0,0 +1,276 @@
+use std::path::{Path, PathBuf};
+
+use ape_bytebeats::{
+    decode_share_url, detect_period, encode_share_url, export_loop, parse_rate,
+    run_bytebeats_synth, run_playlist, BytebeatOptions, BytebeatSource, Dialect, OutputMode,
+    Preset, PresetLibrary, MAX_PERIOD,
+};
+use ape_core::{
+    color_eyre::eyre::{self, eyre},
+    tracing, AudioOutput,
+};
+use clap::{Parser, Subcommand};
+
+use crate::explore::{run_explore, ExploreCmd};
+
+#[derive(Parser, Debug)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub struct BytebeatsCmd {
+    #[command(subcommand)]
+    action: Option<BytebeatsAction>,
+
+    /// Formula, mono or returning the left and right channels
+    #[arg(required_unless_present_any = ["left", "library", "from_url"])]
+    formula: Option<String>,
+
+    /// Formula of the left channel
+    #[arg(long, requires = "right", conflicts_with = "formula")]
+    left: Option<String>,
+
+    /// Formula of the right channel
+    #[arg(long, requires = "left")]
+    right: Option<String>,
+
+    /// Play the right channel this many t ahead of the left one
+    #[arg(long, default_value_t = 0)]
+    stereo_offset: u64,
+
+    /// Formula language (c, lua)
+    #[arg(short, long, default_value = "lua")]
+    lang: Dialect,
+
+    /// Output mode (bytebeat, signed, u<bits>, s<bits>, floatbeat, funcbeat)
+    #[arg(short, long, default_value = "bytebeat")]
+    mode: OutputMode,
+
+    /// Formula rate, in Hz or with shortcuts like 8k, 11k, 22k, 44.1k
+    #[arg(short, long, default_value = "8k", value_parser = parse_rate)]
+    rate: u32,
+
+    /// Value of t to start from
+    #[arg(long, default_value_t = 0)]
+    start: u64,
+
+    /// Preset library file
+    #[arg(long, conflicts_with_all = ["formula", "left"])]
+    library: Option<PathBuf>,
+
+    /// Preset to play from the library, with its own options
+    #[arg(short, long, requires = "library")]
+    preset: Option<String>,
+
+    /// Only use the library presets with this tag
+    #[arg(long, requires = "library", conflicts_with = "preset")]
+    tag: Option<String>,
+
+    /// Seconds each preset plays when cycling through the library
+    #[arg(long, default_value_t = 30.0)]
+    preset_duration: f64,
+
+    /// Crossfade between presets, in seconds
+    #[arg(long, default_value_t = 2.0)]
+    crossfade: f64,
+
+    /// Export each library preset to a .wav file in this directory
+    #[arg(long, requires = "library", conflicts_with = "preset")]
+    export_dir: Option<PathBuf>,
+
+    /// Play a formula shared as a web player link, with its mode and rate
+    #[arg(long, conflicts_with_all = ["formula", "left", "library"])]
+    from_url: Option<String>,
+
+    /// Print the web player link of the formula or presets instead of playing them
+    #[arg(long, conflicts_with = "export_dir")]
+    to_url: bool,
+
+    /// Print the period of the formula, in t
+    #[arg(long, conflicts_with_all = ["export_dir", "to_url"])]
+    find_period: bool,
+
+    /// Export this many periods of the formula to the .wav file, as a seamless loop
+    #[arg(
+        long,
+        conflicts_with_all = ["export_dir", "to_url", "find_period"],
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    loops: Option<u64>,
+
+    /// Longest period searched, in t
+    #[arg(long, default_value_t = 1 << 20, value_parser = clap::value_parser!(u64).range(1..=MAX_PERIOD))]
+    max_period: u64,
+}
+
+#[derive(Subcommand, Debug)]
+enum BytebeatsAction {
+    /// Generate random C formulas to audition and keep
+    Explore(ExploreCmd),
+}
+
+impl BytebeatsCmd {
+    /// Set when the command writes files, prints or plays on its own, instead of playing.
+    pub fn is_offline(&self) -> bool {
+        self.action.is_some()
+            || self.export_dir.is_some()
+            || self.to_url
+            || self.find_period
+            || self.loops.is_some()
+    }
+
+    /// Formula given on the command line, as a link or as a preset.
+    fn source(&self) -> eyre::Result<(BytebeatSource, BytebeatOptions)> {
+        if let Some(url) = &self.from_url {
+            return decode_share_url(url);
+        }
+        if self.preset.is_some() {
+            let preset = self.presets()?.remove(0);
+            return Ok((preset.source, preset.options));
+        }
+
+        let options = BytebeatOptions {
+            dialect: self.lang,
+            mode: self.mode,
+            rate: self.rate,
+            start: self.start,
+            stereo_offset: self.stereo_offset,
+        };
+        let source = match (&self.formula, &self.left, &self.right) {
+            (_, Some(left), Some(right)) => BytebeatSource::Split(left.clone(), right.clone()),
+            (formula, _, _) => BytebeatSource::Single(formula.clone().unwrap_or_default()),
+        };
+        Ok((source, options))
+    }
+
+    /// Presets of the library, filtered by name or tag.
+    fn presets(&self) -> eyre::Result<Vec<Preset>> {
+        let path = self
+            .library
+            .as_ref()
+            .ok_or_else(|| eyre!("no preset library"))?;
+        let library = PresetLibrary::from_file(path)?;
+
+        let presets: Vec<_> = match (&self.preset, &self.tag) {
+            (Some(name), _) => vec![library
+                .get(name)
+                .ok_or_else(|| eyre!("unknown preset '{name}'"))?
+                .clone()],
+            (None, Some(tag)) => library
+                .presets
+                .into_iter()
+                .filter(|preset| preset.has_tag(tag))
+                .collect(),
+            (None, None) => library.presets,
+        };
+
+        if presets.is_empty() {
+            return Err(eyre!("no presets to play"));
+        }
+        Ok(presets)
+    }
+}
+
+pub fn run_bytebeats(output: AudioOutput, cmd: BytebeatsCmd) -> eyre::Result<()> {
+    if cmd.library.is_some() {
+        let presets = cmd.presets()?;
+        return match presets.as_slice() {
+            [preset] if cmd.preset.is_some() => {
+                run_bytebeats_synth(output, &preset.source, &preset.options)
+            }
+            presets => run_playlist(output, presets, cmd.preset_duration, cmd.crossfade),
+        };
+    }
+
+    let (source, options) = cmd.source()?;
+    run_bytebeats_synth(output, &source, &options)
+}
+
+/// Print links or periods, or export loops and presets, with outputs built by `wav_output`.
+pub fn run_bytebeats_offline(
+    cmd: &BytebeatsCmd,
+    wav: Option<&Path>,
+    sample_rate: Option<u32>,
+    wav_output: impl Fn(PathBuf) -> AudioOutput,
+) -> eyre::Result<()> {
+    if let Some(BytebeatsAction::Explore(explore)) = &cmd.action {
+        return run_explore(explore);
+    }
+
+    if let Some(dir) = &cmd.export_dir {
+        return export_library(cmd, dir, wav_output);
+    }
+
+    if cmd.find_period {
+        let (source, options) = cmd.source()?;
+        let period = detect_period(&source, &options, cmd.max_period)
+            .map_err(|error| eyre!("invalid formula\n{}", error.highlight()))?;
+        match period {
+            Some(period) => println!(
+                "{period} t ({:.3} s at {} Hz)",
+                period as f64 / options.rate as f64,
+                options.rate
+            ),
+            None => println!("no period up to {} t", cmd.max_period),
+        }
+        return Ok(());
+    }
+
+    if let Some(periods) = cmd.loops {
+        let path = wav.ok_or_else(|| eyre!("exporting a loop needs a .wav file"))?;
+        let (source, options) = cmd.source()?;
+        let period = export_loop(
+            path,
+            &source,
+            &options,
+            periods,
+            cmd.max_period,
+            sample_rate,
+        )?;
+        tracing::info!(
+            "exported {periods} period(s) of {period} t to {}",
+            path.display()
+        );
+        return Ok(());
+    }
+
+    if cmd.library.is_none() {
+        let (source, options) = cmd.source()?;
+        println!("{}", encode_share_url(&source, &options)?);
+        return Ok(());
+    }
+
+    for preset in cmd.presets()? {
+        match encode_share_url(&preset.source, &preset.options) {
+            Ok(url) => println!("{}: {url}", preset.name),
+            Err(error) => tracing::warn!("skipping preset '{}': {error}", preset.name),
+        }
+    }
+    Ok(())
+}
+
+/// Render each preset to `<name>.wav`.
+fn export_library(
+    cmd: &BytebeatsCmd,
+    dir: &Path,
+    wav_output: impl Fn(PathBuf) -> AudioOutput,
+) -> eyre::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    for preset in cmd.presets()? {
+        let file_name: String = preset
+            .name
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let path = dir.join(format!("{file_name}.wav"));
+
+        tracing::info!("exporting '{}' to {}", preset.name, path.display());
+        run_bytebeats_synth(wav_output(path), &preset.source, &preset.options)
+            .map_err(|error| eyre!("preset '{}': {error}", preset.name))?;
+    }
+
+    Ok(())
+}
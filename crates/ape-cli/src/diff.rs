@@ -0,0 +1,89 @@
+use std::path::PathBuf;
+
+use ape_core::{
+    color_eyre::eyre::{self, eyre},
+    diff::{diff, DiffOptions},
+    export::export_frames_to_wav,
+    hound::{SampleFormat, WavSpec},
+    player::AudioFile,
+    signal::db_to_amplitude,
+};
+use clap::Parser;
+
+#[derive(Parser, Debug)]
+pub struct DiffCmd {
+    /// Reference .wav file
+    first: PathBuf,
+
+    /// .wav file compared to the reference
+    second: PathBuf,
+
+    /// Largest latency between the files searched, in samples, before comparing them
+    #[arg(long)]
+    max_latency: Option<usize>,
+
+    /// Differences up to this level are ignored, in dBFS
+    #[arg(short, long, allow_negative_numbers = true)]
+    tolerance: Option<f64>,
+
+    /// Write the difference of the second file from the first one to a .wav file
+    #[arg(short, long)]
+    residual: Option<PathBuf>,
+
+    /// Fail when the files differ, for scripts
+    #[arg(long)]
+    check: bool,
+}
+
+pub fn run_diff(cmd: &DiffCmd) -> eyre::Result<()> {
+    let first = AudioFile::from_wav(&cmd.first)?;
+    let second = AudioFile::from_wav(&cmd.second)?;
+    let options = DiffOptions {
+        max_latency: cmd.max_latency,
+        tolerance: cmd.tolerance.map_or(0.0, |db| db_to_amplitude(db) as f32),
+    };
+    let result = diff(&first, &second, &options)?;
+    let seconds = |frame: isize| frame as f64 / result.sample_rate as f64;
+
+    if cmd.max_latency.is_some() {
+        println!(
+            "latency: {} samples ({:.3} ms)",
+            result.offset,
+            seconds(result.offset) * 1_000.0
+        );
+    }
+    println!(
+        "compared: {} frames ({:.3} s)",
+        result.residual.len(),
+        seconds(result.residual.len() as isize)
+    );
+    println!("max difference: {:.2} dBFS", result.max_db());
+    println!("rms difference: {:.2} dBFS", result.rms_db());
+    match result.first_difference {
+        Some((frame, channel)) => println!(
+            "first difference: sample {frame} ({:.6} s), {} channel",
+            seconds(frame),
+            ["left", "right"][channel]
+        ),
+        None => println!("null: no difference"),
+    }
+
+    if let Some(path) = &cmd.residual {
+        // Floats keep differences below the 16-bit noise floor
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate: result.sample_rate,
+            bits_per_sample: 32,
+            sample_format: SampleFormat::Float,
+        };
+        let mut frames = result.residual.iter();
+        export_frames_to_wav(path, spec, result.residual.len(), || {
+            frames.next().copied().unwrap_or_default()
+        })?;
+    }
+
+    if cmd.check && result.first_difference.is_some() {
+        return Err(eyre!("the files differ"));
+    }
+    Ok(())
+}
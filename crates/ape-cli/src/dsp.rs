@@ -0,0 +1,117 @@
+use ape_core::{
+    color_eyre::eyre::{self, eyre},
+    dsp::{DspOptions, DspSynth, FilterMode, LfoTarget},
+    note::parse_frequency,
+    process_stream,
+    sequencer::{parse_division, parse_steps},
+    voice::{Adsr, Waveform},
+    AudioOutput,
+};
+use clap::Parser;
+
+#[derive(Parser, Debug)]
+pub struct DspCmd {
+    /// Waveform (sine, saw, square, triangle, pulse)
+    #[arg(long, default_value = "pulse")]
+    waveform: Waveform,
+
+    /// Frequency in Hz, or note name like A4
+    #[arg(short, long, default_value = "440", value_parser = parse_frequency)]
+    freq: f64,
+
+    /// LFO rate, in Hz
+    #[arg(long, default_value_t = 0.2)]
+    lfo_rate: f64,
+
+    /// LFO depth, the fraction of the pulse width range, semitones, octaves of cutoff or the
+    /// fraction of the volume, depending on the target
+    #[arg(long, default_value_t = 1.0)]
+    lfo_depth: f64,
+
+    /// Parameter modulated by the LFO (width, pitch, cutoff, volume)
+    #[arg(long, default_value = "width")]
+    lfo_target: LfoTarget,
+
+    /// Filter (lowpass, highpass, bandpass, moog)
+    #[arg(long)]
+    filter: Option<FilterMode>,
+
+    /// Filter cutoff, in Hz
+    #[arg(long, default_value_t = 2000.0)]
+    cutoff: f64,
+
+    /// Filter Q, from 0 to 1 for the moog filter
+    #[arg(long, default_value_t = 0.7)]
+    resonance: f64,
+
+    /// Envelope attack, in seconds
+    #[arg(long, default_value_t = Adsr::default().attack)]
+    attack: f64,
+
+    /// Envelope decay, in seconds
+    #[arg(long, default_value_t = Adsr::default().decay)]
+    decay: f64,
+
+    /// Envelope sustain level, from 0 to 1
+    #[arg(long, default_value_t = Adsr::default().sustain)]
+    sustain: f64,
+
+    /// Envelope release, in seconds
+    #[arg(long, default_value_t = Adsr::default().release)]
+    release: f64,
+
+    /// Volume, from 0 to 1
+    #[arg(long, default_value_t = 1.0)]
+    volume: f64,
+
+    /// Notes played in a loop, in the sequencer step syntax (e.g. "C3 . D#3:v80 G2 -")
+    #[arg(short, long)]
+    notes: Option<String>,
+
+    /// Tempo of the notes, in BPM
+    #[arg(long, default_value_t = 120.0)]
+    tempo: f64,
+
+    /// Step length of the notes, as a division like 1/16
+    #[arg(long, default_value = "1/16", value_parser = parse_division)]
+    step: f64,
+
+    /// Seed of the step probabilities, for reproducible renders
+    #[arg(long)]
+    seed: Option<u64>,
+}
+
+pub fn run_dsp(output: AudioOutput, cmd: DspCmd) -> eyre::Result<()> {
+    let mut steps = vec![];
+    if let Some(notes) = &cmd.notes {
+        parse_steps(&mut steps, notes).map_err(|error| eyre!(error))?;
+    }
+    if cmd.tempo <= 0.0 || !cmd.tempo.is_finite() {
+        return Err(eyre!("the tempo must be positive and finite"));
+    }
+
+    let options = DspOptions {
+        waveform: cmd.waveform,
+        frequency: cmd.freq,
+        lfo_rate: cmd.lfo_rate,
+        lfo_depth: cmd.lfo_depth,
+        lfo_target: cmd.lfo_target,
+        filter: cmd.filter,
+        cutoff: cmd.cutoff,
+        resonance: cmd.resonance,
+        adsr: Adsr {
+            attack: cmd.attack,
+            decay: cmd.decay,
+            sustain: cmd.sustain,
+            release: cmd.release,
+        },
+        volume: cmd.volume,
+        steps,
+        // Steps are given in quarter notes
+        step_duration: cmd.step * 60.0 / cmd.tempo,
+        seed: cmd.seed,
+    };
+
+    let mut synth = DspSynth::new(options, output.sample_rate())?;
+    process_stream(output, move || synth.tick())
+}
@@ -0,0 +1,170 @@
+use std::{
+    fs::OpenOptions,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+};
+
+use ape_bytebeats::{
+    BytebeatGenerator, BytebeatOptions, BytebeatSource, Dialect, Explorer, Expr, ExprFormula,
+    Preset, PresetLibrary,
+};
+use ape_core::{
+    color_eyre::eyre::{self, eyre},
+    start_stream_thread, AudioOutput,
+};
+use clap::Parser;
+
+/// Attempts to find a candidate passing the checks.
+const ATTEMPTS: usize = 10_000;
+
+#[derive(Parser, Debug)]
+pub struct ExploreCmd {
+    /// C formulas to mutate and cross, instead of generating new ones
+    #[arg(long = "from")]
+    parents: Vec<String>,
+
+    /// Number of candidates
+    #[arg(short = 'n', long, default_value_t = 10)]
+    count: usize,
+
+    /// Preset library where kept formulas are added
+    #[arg(long)]
+    save: Option<PathBuf>,
+
+    /// Keep every candidate, without playing them
+    #[arg(long, requires = "save")]
+    auto: bool,
+
+    /// Seed, for reproducible explorations
+    #[arg(long)]
+    seed: Option<u64>,
+}
+
+/// What to do with the candidate being played.
+enum Choice {
+    Next,
+    Keep,
+    /// Explore mutations of the candidate.
+    Mutate,
+    Quit,
+}
+
+fn ask() -> eyre::Result<Choice> {
+    loop {
+        print!("[enter] next, [k]eep, [m]utate it, [q]uit: ");
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+        if io::stdin().lock().read_line(&mut line)? == 0 {
+            return Ok(Choice::Quit);
+        }
+        match line.trim() {
+            "" | "n" => return Ok(Choice::Next),
+            "k" => return Ok(Choice::Keep),
+            "m" => return Ok(Choice::Mutate),
+            "q" => return Ok(Choice::Quit),
+            _ => (),
+        }
+    }
+}
+
+/// Add a formula to the library file, named after the number of presets it holds.
+fn save(path: &Path, formula: &str) -> eyre::Result<()> {
+    let count = if path.exists() {
+        PresetLibrary::from_file(path)?.presets.len()
+    } else {
+        0
+    };
+
+    let mut preset = Preset::new(&format!("explored-{}", count + 1));
+    preset.tags = vec!["explored".into()];
+    preset.options.dialect = Dialect::C;
+    preset.source = BytebeatSource::Single(formula.into());
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    write!(file, "\n{preset}")?;
+    Ok(())
+}
+
+pub fn run_explore(cmd: &ExploreCmd) -> eyre::Result<()> {
+    let mut parents: Vec<Expr> = cmd
+        .parents
+        .iter()
+        .map(|formula| {
+            let formula = ExprFormula::new(formula)
+                .map_err(|error| eyre!("invalid formula\n{}", error.highlight()))?;
+            Ok(formula.channels()[0].clone())
+        })
+        .collect::<eyre::Result<_>>()?;
+    let mut explorer = Explorer::new(cmd.seed);
+
+    // Candidates play one after the other on a single stream
+    let (generators, new_generators) = mpsc::channel::<BytebeatGenerator>();
+    let running = Arc::new(AtomicBool::new(true));
+    let stream = if cmd.auto {
+        None
+    } else {
+        let output = AudioOutput::new_direct()?;
+        let sample_rate = output.sample_rate();
+        let mut generator = None;
+        let sample_fn = move || {
+            if let Ok(new_generator) = new_generators.try_recv() {
+                generator = Some(new_generator);
+            }
+            generator
+                .as_mut()
+                .map_or([0.0; 2], BytebeatGenerator::next_frame)
+        };
+        Some((
+            start_stream_thread(output, sample_fn, running.clone())?,
+            sample_rate,
+        ))
+    };
+
+    for index in 1..=cmd.count {
+        let candidate = explorer
+            .candidate(&parents, ATTEMPTS)
+            .ok_or_else(|| eyre!("no candidate found in {ATTEMPTS} attempts"))?;
+        let formula = candidate.to_string();
+        println!("{index}/{}: {formula}", cmd.count);
+
+        let choice = match &stream {
+            None => Choice::Keep,
+            Some((_, sample_rate)) => {
+                let options = BytebeatOptions {
+                    dialect: Dialect::C,
+                    ..Default::default()
+                };
+                let source = BytebeatSource::Single(formula.clone());
+                let generator = BytebeatGenerator::new(&source, &options, *sample_rate)?;
+                generators
+                    .send(generator)
+                    .map_err(|_| eyre!("the audio thread stopped"))?;
+                ask()?
+            }
+        };
+
+        match choice {
+            Choice::Next => (),
+            Choice::Keep => {
+                if let Some(path) = &cmd.save {
+                    save(path, &formula)?;
+                }
+            }
+            Choice::Mutate => parents = vec![candidate],
+            Choice::Quit => break,
+        }
+    }
+
+    running.store(false, Ordering::Relaxed);
+    if let Some((handle, _)) = stream {
+        handle
+            .join()
+            .map_err(|_| eyre!("the audio thread panicked"))?;
+    }
+    Ok(())
+}
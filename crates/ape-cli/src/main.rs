@@ -1,12 +1,32 @@
+mod bytebeats;
+mod diff;
+mod dsp;
+mod explore;
+mod measure;
+mod noise;
+mod play;
+mod script;
+mod seq;
+mod signal;
+
 use std::path::PathBuf;
 
-use ape_bytebeats::run_bytebeats_synth;
 use ape_core::{
     color_eyre::{self, eyre},
-    dsp::{build_dsp_chain, sample_noise_fn},
-    process_stream, AudioOutput, WavOutput,
+    tracing, AudioOutput, WavOutput,
 };
+use bytebeats::{run_bytebeats, run_bytebeats_offline, BytebeatsCmd};
 use clap::{Parser, Subcommand};
+use diff::{run_diff, DiffCmd};
+use dsp::{run_dsp, DspCmd};
+use measure::{run_measure, MeasureCmd};
+use noise::{run_noise, NoiseCmd};
+use play::{run_play, PlayCmd};
+use script::{run_script, ScriptCmd};
+use seq::{run_seq, SeqCmd};
+use signal::{run_signal, SignalCmd};
+use tracing::Level;
+use tracing_subscriber::{filter::Targets, prelude::*};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -30,47 +50,47 @@ struct Args {
 
 #[derive(Subcommand, Debug)]
 enum SubCmd {
-    Bytebeats(BytebeatsCmd),
-    Noise,
-    Dsp,
+    Bytebeats(Box<BytebeatsCmd>),
+    Noise(NoiseCmd),
+    Dsp(DspCmd),
+    Seq(SeqCmd),
+    Script(ScriptCmd),
+    Play(PlayCmd),
+    Signal(SignalCmd),
+    Measure(MeasureCmd),
+    Diff(DiffCmd),
 }
 
-#[derive(Parser, Debug)]
-struct BytebeatsCmd {
-    /// Formula
-    formula: String,
+fn wav_output(path: PathBuf, args: &Args) -> AudioOutput {
+    AudioOutput::Wav(WavOutput {
+        path,
+        duration: args.duration.unwrap_or(3),
+        spec: ape_core::hound::WavSpec {
+            bits_per_sample: 16,
+            channels: 2,
+            sample_format: ape_core::hound::SampleFormat::Int,
+            sample_rate: args.sample_rate.unwrap_or(44_100),
+        },
+    })
 }
 
 fn build_audio_output(args: &Args) -> eyre::Result<AudioOutput> {
     if let Some(path) = &args.wav {
-        Ok(AudioOutput::Wav(WavOutput {
-            path: path.into(),
-            duration: args.duration.unwrap_or(3),
-            spec: ape_core::hound::WavSpec {
-                bits_per_sample: 16,
-                channels: 2,
-                sample_format: ape_core::hound::SampleFormat::Int,
-                sample_rate: args.sample_rate.unwrap_or(44_100),
-            },
-        }))
+        Ok(wav_output(path.into(), args))
     } else {
         AudioOutput::new_direct()
     }
 }
 
-fn run_dsp_synth(output: AudioOutput) -> eyre::Result<()> {
-    let mut chain = build_dsp_chain(output.sample_rate());
-    let sample_fn = move || {
-        let v = chain.get_stereo();
-        vec![v.0 as f32, v.1 as f32]
-    };
-
-    // let sample_fn = sample_noise_fn;
-    process_stream(output, sample_fn)
-}
-
 fn setup_logging() -> eyre::Result<()> {
-    tracing_subscriber::fmt().init();
+    // The formulas JIT logs all the code it compiles
+    let filter = Targets::new()
+        .with_default(Level::INFO)
+        .with_target("cranelift_jit", Level::WARN);
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .with(filter)
+        .init();
 
     Ok(())
 }
@@ -80,18 +100,51 @@ fn main() -> eyre::Result<()> {
     setup_logging()?;
 
     let args = Args::parse();
+
+    // Exports write their own files, and links and periods are only printed
+    if let SubCmd::Bytebeats(bb) = &args.cmd {
+        if bb.is_offline() {
+            let (wav, sample_rate) = (args.wav.as_deref(), args.sample_rate);
+            return run_bytebeats_offline(bb, wav, sample_rate, |path| wav_output(path, &args));
+        }
+    }
+
+    // Measurements are only printed or written
+    if let SubCmd::Measure(measure) = &args.cmd {
+        return run_measure(measure, args.sample_rate.unwrap_or(44_100));
+    }
+
+    // Comparisons only read files
+    if let SubCmd::Diff(diff) = &args.cmd {
+        return run_diff(diff);
+    }
+
     let output = build_audio_output(&args)?;
 
     match args.cmd {
         SubCmd::Bytebeats(bb) => {
-            run_bytebeats_synth(output, bb.formula)?;
+            run_bytebeats(output, *bb)?;
+        }
+        SubCmd::Dsp(dsp) => {
+            run_dsp(output, dsp)?;
+        }
+        SubCmd::Noise(noise) => {
+            run_noise(output, noise)?;
+        }
+        SubCmd::Seq(seq) => {
+            run_seq(output, seq)?;
+        }
+        SubCmd::Script(script) => {
+            run_script(output, script)?;
         }
-        SubCmd::Dsp => {
-            run_dsp_synth(output)?;
+        SubCmd::Play(play) => {
+            run_play(output, play)?;
         }
-        SubCmd::Noise => {
-            process_stream(output, sample_noise_fn)?;
+        SubCmd::Signal(signal) => {
+            run_signal(output, signal)?;
         }
+        SubCmd::Measure(_) => unreachable!("measurements run without an output"),
+        SubCmd::Diff(_) => unreachable!("comparisons run without an output"),
     }
 
     Ok(())
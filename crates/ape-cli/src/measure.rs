@@ -0,0 +1,192 @@
+use std::{fs, io::Write, path::PathBuf, str::FromStr};
+
+use ape_core::{
+    color_eyre::eyre::{self, eyre},
+    dsp::{build_effect_chain, Effect},
+    measure::{measure, MeasureOptions, Measurement, Stimulus},
+};
+use clap::Parser;
+use serde_json::json;
+
+#[derive(Parser, Debug)]
+pub struct MeasureCmd {
+    /// Effects measured, applied in order like in the play command (e.g. lowpass:2000)
+    #[arg(short, long = "effect")]
+    effects: Vec<Effect>,
+
+    /// Stimulus (sweep, impulse)
+    #[arg(long, default_value = "sweep")]
+    stimulus: Stimulus,
+
+    /// Lowest frequency of the sweep and response, in Hz
+    #[arg(long, default_value_t = 20.0)]
+    from: f64,
+
+    /// Highest frequency of the sweep and response, in Hz
+    #[arg(long, default_value_t = 20_000.0)]
+    to: f64,
+
+    /// Sweep duration, in seconds
+    #[arg(long, default_value_t = 2.0)]
+    sweep_duration: f64,
+
+    /// Impulse response length, in samples
+    #[arg(long, default_value_t = 8192)]
+    ir_length: usize,
+
+    /// Stimulus level, in dBFS
+    #[arg(short, long, default_value_t = -6.0, allow_negative_numbers = true)]
+    level: f64,
+
+    /// Frequency response points per octave
+    #[arg(long, default_value_t = 12)]
+    points_per_octave: usize,
+
+    /// Fundamentals of the THD measurements, in Hz
+    #[arg(long = "thd", default_values_t = [1000.0])]
+    thd_frequencies: Vec<f64>,
+
+    /// Output channel measured (0 for left, 1 for right)
+    #[arg(long, default_value_t = 0)]
+    channel: usize,
+
+    /// Output format (json, or csv for one table)
+    #[arg(short, long, default_value = "json")]
+    format: Format,
+
+    /// Table written as CSV (response, impulse, thd)
+    #[arg(long, default_value = "response")]
+    table: Table,
+
+    /// File written, instead of the standard output
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    Json,
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            other => Err(format!("unknown format '{other}'")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Table {
+    Response,
+    Impulse,
+    Thd,
+}
+
+impl FromStr for Table {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "response" => Ok(Self::Response),
+            "impulse" => Ok(Self::Impulse),
+            "thd" => Ok(Self::Thd),
+            other => Err(format!("unknown table '{other}'")),
+        }
+    }
+}
+
+fn to_json(measurement: &Measurement) -> String {
+    let response: Vec<_> = measurement
+        .response
+        .iter()
+        .map(|point| {
+            json!({
+                "frequency": point.frequency,
+                "magnitude": point.magnitude,
+                "phase": point.phase,
+            })
+        })
+        .collect();
+    let distortion: Vec<_> = measurement
+        .distortion
+        .iter()
+        .map(|distortion| {
+            json!({
+                "frequency": distortion.frequency,
+                "thd": distortion.thd,
+                "harmonics": distortion.harmonics,
+            })
+        })
+        .collect();
+
+    let value = json!({
+        "sampleRate": measurement.sample_rate,
+        "impulseResponse": measurement.impulse_response,
+        "response": response,
+        "thd": distortion,
+    });
+    format!("{value:#}\n")
+}
+
+fn to_csv(measurement: &Measurement, table: Table) -> String {
+    let mut csv = String::new();
+    match table {
+        Table::Response => {
+            csv.push_str("frequency,magnitude_db,phase_deg\n");
+            for point in &measurement.response {
+                csv += &format!("{},{},{}\n", point.frequency, point.magnitude, point.phase);
+            }
+        }
+        Table::Impulse => {
+            csv.push_str("sample,time,value\n");
+            for (index, value) in measurement.impulse_response.iter().enumerate() {
+                let time = index as f64 / measurement.sample_rate as f64;
+                csv += &format!("{index},{time},{value}\n");
+            }
+        }
+        Table::Thd => {
+            csv.push_str("frequency,thd_percent,thd_db\n");
+            for distortion in &measurement.distortion {
+                let (percent, db) = match distortion.thd {
+                    Some(thd) => ((thd * 100.0).to_string(), (20.0 * thd.log10()).to_string()),
+                    None => (String::new(), String::new()),
+                };
+                csv += &format!("{},{percent},{db}\n", distortion.frequency);
+            }
+        }
+    }
+    csv
+}
+
+pub fn run_measure(cmd: &MeasureCmd, sample_rate: u32) -> eyre::Result<()> {
+    let options = MeasureOptions {
+        stimulus: cmd.stimulus,
+        sample_rate,
+        start: cmd.from,
+        end: cmd.to,
+        sweep_duration: cmd.sweep_duration,
+        ir_length: cmd.ir_length,
+        level: cmd.level,
+        points_per_octave: cmd.points_per_octave,
+        thd_frequencies: cmd.thd_frequencies.clone(),
+        channel: cmd.channel,
+    };
+    let mut chain = build_effect_chain(&cmd.effects, sample_rate)?;
+    let measurement = measure(chain.as_mut(), &options)?;
+
+    let text = match cmd.format {
+        Format::Json => to_json(&measurement),
+        Format::Csv => to_csv(&measurement, cmd.table),
+    };
+    match &cmd.output {
+        Some(path) => fs::write(path, text)
+            .map_err(|error| eyre!("could not write {}: {error}", path.display())),
+        None => Ok(std::io::stdout().write_all(text.as_bytes())?),
+    }
+}
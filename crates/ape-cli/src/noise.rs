@@ -0,0 +1,42 @@
+use ape_core::{
+    color_eyre::eyre,
+    noise::{NoiseColor, NoiseGenerator, NoiseOptions},
+    process_stream, AudioOutput,
+};
+use clap::Parser;
+
+#[derive(Parser, Debug)]
+pub struct NoiseCmd {
+    /// Color (white, pink, brown, blue, violet, velvet)
+    #[arg(short, long, default_value = "white")]
+    color: NoiseColor,
+
+    /// Seed, for reproducible renders
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Amplitude
+    #[arg(short, long, default_value_t = 1.0)]
+    amplitude: f32,
+
+    /// Stereo decorrelation, from 0 (mono) to 1 (independent channels)
+    #[arg(long, default_value_t = 0.0)]
+    decorrelation: f32,
+
+    /// Velvet noise density, in impulses per second
+    #[arg(long, default_value_t = 2000.0)]
+    density: f32,
+}
+
+pub fn run_noise(output: AudioOutput, cmd: NoiseCmd) -> eyre::Result<()> {
+    let options = NoiseOptions {
+        color: cmd.color,
+        seed: cmd.seed,
+        amplitude: cmd.amplitude,
+        decorrelation: cmd.decorrelation,
+        density: cmd.density,
+    };
+
+    let mut generator = NoiseGenerator::new(&options, output.sample_rate());
+    process_stream(output, move || generator.next_frame())
+}
@@ -0,0 +1,103 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use ape_core::{
+    color_eyre::eyre::{self, eyre},
+    dsp::{build_effect_chain, effect_chain_tail, Effect},
+    export::export_frames_to_wav,
+    player::{AudioFile, FilePlayer, PlayerOptions},
+    process_stream, start_stream_thread, AudioOutput,
+};
+use clap::Parser;
+
+#[derive(Parser, Debug)]
+pub struct PlayCmd {
+    /// .wav file
+    file: PathBuf,
+
+    /// Play the file in a loop
+    #[arg(short, long = "loop")]
+    looping: bool,
+
+    /// Position to start from, in seconds
+    #[arg(long, default_value_t = 0.0)]
+    start: f64,
+
+    /// Gain, in dB
+    #[arg(short, long, default_value_t = 0.0, allow_negative_numbers = true)]
+    gain: f32,
+
+    /// Effects applied in order, like lowpass:2000, delay:0.3:0.5 or reverb (lowpass, highpass,
+    /// bandpass, notch and moog take a frequency and a q, delay a time and a feedback, reverb a
+    /// room size, a time and a mix, and gain dB; chorus and clip take no parameters). Only effect
+    /// chains can process files, DSP synth patches have no input
+    #[arg(short, long = "effect")]
+    effects: Vec<Effect>,
+
+    /// Time played after the end of the file without a loop, letting effects ring out, in
+    /// seconds [default: until delays and reverbs decay]
+    #[arg(long)]
+    tail: Option<f64>,
+}
+
+pub fn run_play(output: AudioOutput, cmd: PlayCmd) -> eyre::Result<()> {
+    let file = AudioFile::from_wav(&cmd.file)?;
+    let options = PlayerOptions {
+        looping: cmd.looping,
+        start: cmd.start,
+        gain: 10_f32.powf(cmd.gain / 20.0),
+    };
+    let sample_rate = output.sample_rate();
+    let mut player = FilePlayer::new(file, options, sample_rate)?;
+    let mut chain = build_effect_chain(&cmd.effects, sample_rate)?;
+
+    let tail = cmd.tail.unwrap_or_else(|| effect_chain_tail(&cmd.effects));
+    if !tail.is_finite() || tail < 0.0 {
+        return Err(eyre!("the tail must be a positive time"));
+    }
+    let tail_frames = (tail * sample_rate as f64).ceil() as usize;
+    let frames = player.remaining_frames() + tail_frames;
+    let mut remaining_frames = frames;
+    let finished = Arc::new(AtomicBool::new(false));
+    let player_finished = finished.clone();
+    let sample_fn = move || {
+        let [left, right] = player.next_frame();
+        remaining_frames = remaining_frames.saturating_sub(1);
+        if remaining_frames == 0 {
+            player_finished.store(true, Ordering::Relaxed);
+        }
+        let (left, right) = chain.filter_stereo(left as f64, right as f64);
+        [left as f32, right as f32]
+    };
+
+    if cmd.looping {
+        return process_stream(output, sample_fn);
+    }
+
+    // Without a loop, exports hold the whole file and its tail, and playback stops after them
+    match output {
+        AudioOutput::Wav(params) => {
+            export_frames_to_wav(&params.path, params.spec, frames, sample_fn)
+        }
+        output => {
+            let running = Arc::new(AtomicBool::new(true));
+            let handle = start_stream_thread(output, sample_fn, running.clone())?;
+            while !finished.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(10));
+            }
+            // Lets the device play its last buffer
+            thread::sleep(Duration::from_millis(200));
+            running.store(false, Ordering::Relaxed);
+            handle
+                .join()
+                .map_err(|_| eyre!("the audio thread panicked"))
+        }
+    }
+}
@@ -0,0 +1,20 @@
+use std::path::PathBuf;
+
+use ape_bytebeats::run_script_synth;
+use ape_core::{
+    color_eyre::eyre::{self, WrapErr},
+    AudioOutput,
+};
+use clap::Parser;
+
+#[derive(Parser, Debug)]
+pub struct ScriptCmd {
+    /// Lua script, defining `init(sample_rate)` and `process(frames)` or `tick()`
+    file: PathBuf,
+}
+
+pub fn run_script(output: AudioOutput, cmd: ScriptCmd) -> eyre::Result<()> {
+    let source = std::fs::read_to_string(&cmd.file)
+        .wrap_err_with(|| format!("could not read {}", cmd.file.display()))?;
+    run_script_synth(output, &source)
+}
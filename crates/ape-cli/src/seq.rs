@@ -0,0 +1,23 @@
+use std::path::PathBuf;
+
+use ape_core::{
+    color_eyre::eyre,
+    process_stream,
+    sequencer::{Sequencer, Song},
+    AudioOutput,
+};
+use clap::Parser;
+
+#[derive(Parser, Debug)]
+pub struct SeqCmd {
+    /// Song file
+    file: PathBuf,
+}
+
+pub fn run_seq(output: AudioOutput, cmd: SeqCmd) -> eyre::Result<()> {
+    let song = Song::from_file(&cmd.file)?;
+    let mut sequencer = Sequencer::new(song, output.sample_rate() as f64)?;
+    sequencer.play();
+
+    process_stream(output, move || sequencer.tick())
+}
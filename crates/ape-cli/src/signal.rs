@@ -0,0 +1,146 @@
+use ape_core::{
+    color_eyre::eyre::{self, eyre},
+    export::export_frames_to_wav,
+    hound::{SampleFormat, WavSpec},
+    note::parse_frequency,
+    process_stream,
+    signal::{Signal, SignalGenerator, SignalOptions},
+    AudioOutput,
+};
+use clap::{Parser, Subcommand};
+
+#[derive(Parser, Debug)]
+pub struct SignalCmd {
+    #[command(subcommand)]
+    signal: SignalKind,
+
+    /// Level in dBFS, the peak level for tones and impulses and the RMS level for pink noise
+    #[arg(short, long, default_value_t = -20.0, allow_negative_numbers = true, global = true)]
+    level: f64,
+
+    /// Duration of the signal, in seconds, repeated when playing
+    #[arg(short = 'L', long, default_value_t = 5.0, global = true)]
+    length: f64,
+
+    /// Bits per sample of .wav exports (16, 24, or 32 for floats)
+    #[arg(long, default_value_t = 24, global = true)]
+    bits: u16,
+}
+
+#[derive(Subcommand, Debug)]
+enum SignalKind {
+    /// Sine tone
+    Sine {
+        /// Frequency in Hz, or note name like A4
+        #[arg(short, long, default_value = "1000", value_parser = parse_frequency)]
+        freq: f64,
+    },
+    /// Sine sweep lasting the whole signal, exponential by default
+    Sweep {
+        /// Start frequency, in Hz
+        #[arg(long, default_value_t = 20.0)]
+        from: f64,
+
+        /// End frequency, in Hz
+        #[arg(long, default_value_t = 20_000.0)]
+        to: f64,
+
+        /// Sweep linearly instead of exponentially
+        #[arg(long)]
+        linear: bool,
+    },
+    /// Single sample impulse
+    Impulse {
+        /// Repeat the impulse with this period, in seconds
+        #[arg(long)]
+        period: Option<f64>,
+    },
+    /// Square wave
+    Square {
+        /// Frequency in Hz, or note name like A4
+        #[arg(short, long, default_value = "1000", value_parser = parse_frequency)]
+        freq: f64,
+    },
+    /// Triangle wave
+    Triangle {
+        /// Frequency in Hz, or note name like A4
+        #[arg(short, long, default_value = "1000", value_parser = parse_frequency)]
+        freq: f64,
+    },
+    /// Pink noise, at an exact RMS level over the signal
+    Pink {
+        /// Seed, for reproducible renders
+        #[arg(long)]
+        seed: Option<u64>,
+    },
+    /// DTMF tones of phone keys
+    Dtmf {
+        /// Keys (0-9, A-D, * and #)
+        digits: String,
+
+        /// Duration of each tone, in seconds
+        #[arg(long, default_value_t = 0.2)]
+        tone: f64,
+
+        /// Silence between tones, in seconds
+        #[arg(long, default_value_t = 0.1)]
+        gap: f64,
+    },
+    /// Tone bursts on the left channel, then twice on the right channel, then on both
+    Channels {
+        /// Frequency in Hz, or note name like A4
+        #[arg(short, long, default_value = "1000", value_parser = parse_frequency)]
+        freq: f64,
+    },
+}
+
+impl SignalKind {
+    fn signal(&self) -> Signal {
+        match self {
+            Self::Sine { freq } => Signal::Sine { frequency: *freq },
+            Self::Sweep { from, to, linear } => Signal::Sweep {
+                start: *from,
+                end: *to,
+                linear: *linear,
+            },
+            Self::Impulse { period } => Signal::Impulse { period: *period },
+            Self::Square { freq } => Signal::Square { frequency: *freq },
+            Self::Triangle { freq } => Signal::Triangle { frequency: *freq },
+            Self::Pink { seed } => Signal::PinkNoise { seed: *seed },
+            Self::Dtmf { digits, tone, gap } => Signal::Dtmf {
+                digits: digits.clone(),
+                tone: *tone,
+                gap: *gap,
+            },
+            Self::Channels { freq } => Signal::Channels { frequency: *freq },
+        }
+    }
+}
+
+pub fn run_signal(output: AudioOutput, cmd: SignalCmd) -> eyre::Result<()> {
+    let options = SignalOptions {
+        signal: cmd.signal.signal(),
+        level: cmd.level,
+        duration: cmd.length,
+    };
+    let mut generator = SignalGenerator::new(options, output.sample_rate())?;
+
+    // Exports hold the signal once, at the requested depth
+    match output {
+        AudioOutput::Wav(params) => {
+            let sample_format = match cmd.bits {
+                8 | 16 | 24 => SampleFormat::Int,
+                32 => SampleFormat::Float,
+                bits => return Err(eyre!("unsupported .wav depth: {bits} bits")),
+            };
+            let spec = WavSpec {
+                bits_per_sample: cmd.bits,
+                sample_format,
+                ..params.spec
+            };
+            let frames = generator.frames();
+            export_frames_to_wav(&params.path, spec, frames, move || generator.next_frame())
+        }
+        output => process_stream(output, move || generator.next_frame()),
+    }
+}
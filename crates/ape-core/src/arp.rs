@@ -0,0 +1,337 @@
+use std::{fmt::Display, str::FromStr};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::transport::Transport;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ArpMode {
+    #[default]
+    Up,
+    Down,
+    UpDown,
+    Random,
+    AsPlayed,
+}
+
+impl ArpMode {
+    pub const ALL: [ArpMode; 5] = [
+        Self::Up,
+        Self::Down,
+        Self::UpDown,
+        Self::Random,
+        Self::AsPlayed,
+    ];
+}
+
+impl Display for ArpMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Up => "up",
+                Self::Down => "down",
+                Self::UpDown => "up-down",
+                Self::Random => "random",
+                Self::AsPlayed => "as-played",
+            }
+        )
+    }
+}
+
+impl FromStr for ArpMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|mode| mode.to_string() == s.to_lowercase())
+            .ok_or_else(|| format!("unknown arpeggiator mode '{s}'"))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ArpSettings {
+    pub mode: ArpMode,
+    pub octaves: u32,
+    /// Step length, in quarter notes.
+    pub rate: f64,
+    /// Note length, in fraction of a step.
+    pub gate: f64,
+    /// Keep playing the last chord once all keys are released.
+    pub latch: bool,
+}
+
+impl Default for ArpSettings {
+    fn default() -> Self {
+        Self {
+            mode: ArpMode::Up,
+            octaves: 1,
+            rate: 0.25,
+            gate: 0.5,
+            latch: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArpEvent {
+    NoteOn(u8, f64),
+    NoteOff(u8),
+}
+
+/// Turns held notes into a rhythmic sequence of note events, synced to a transport.
+pub struct Arpeggiator {
+    settings: ArpSettings,
+    /// Keys physically held, in play order.
+    pressed: Vec<u8>,
+    /// Notes arpeggiated, in play order, with their velocity.
+    notes: Vec<(u8, f64)>,
+    sequence: Vec<(u8, f64)>,
+    index: usize,
+    current: Option<u8>,
+    off_at: f64,
+    last_step: Option<i64>,
+    free_position: f64,
+    rng: StdRng,
+}
+
+impl Arpeggiator {
+    pub fn new(settings: ArpSettings) -> Self {
+        Self {
+            settings,
+            pressed: Vec::with_capacity(128),
+            notes: Vec::with_capacity(128),
+            sequence: Vec::with_capacity(1024),
+            index: 0,
+            current: None,
+            off_at: 0.0,
+            last_step: None,
+            free_position: 0.0,
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    pub fn settings(&self) -> ArpSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: ArpSettings) {
+        if settings == self.settings {
+            return;
+        }
+
+        let latch_released = self.settings.latch && !settings.latch;
+        self.settings = settings;
+        if latch_released {
+            self.notes.retain(|(note, _)| self.pressed.contains(note));
+        }
+        self.build_sequence();
+    }
+
+    pub fn note_on(&mut self, note: u8, velocity: f64) {
+        // A new chord replaces the latched one
+        if self.settings.latch && self.pressed.is_empty() {
+            self.notes.clear();
+        }
+
+        if !self.pressed.contains(&note) {
+            self.pressed.push(note);
+        }
+        self.notes.retain(|(n, _)| *n != note);
+        self.notes.push((note, velocity));
+        self.build_sequence();
+    }
+
+    pub fn note_off(&mut self, note: u8) {
+        self.pressed.retain(|n| *n != note);
+        if !self.settings.latch {
+            self.notes.retain(|(n, _)| *n != note);
+            self.build_sequence();
+        }
+    }
+
+    /// Forget all notes, returning the note to stop if one is playing.
+    pub fn clear(&mut self) -> Option<ArpEvent> {
+        self.pressed.clear();
+        self.notes.clear();
+        self.sequence.clear();
+        self.last_step = None;
+        self.current.take().map(ArpEvent::NoteOff)
+    }
+
+    fn build_sequence(&mut self) {
+        self.sequence.clear();
+        self.sequence.extend_from_slice(&self.notes);
+        if self.settings.mode != ArpMode::AsPlayed {
+            self.sequence.sort_unstable_by_key(|(note, _)| *note);
+        }
+
+        let base = self.sequence.len();
+        for octave in 1..self.settings.octaves.max(1) {
+            for index in 0..base {
+                let (note, velocity) = self.sequence[index];
+                let note = note as u32 + octave * 12;
+                if note <= 127 {
+                    self.sequence.push((note as u8, velocity));
+                }
+            }
+        }
+
+        match self.settings.mode {
+            ArpMode::Down => self.sequence.reverse(),
+            ArpMode::UpDown => {
+                // Do not repeat the top and bottom notes
+                let len = self.sequence.len();
+                if len > 2 {
+                    for index in (1..len - 1).rev() {
+                        self.sequence.push(self.sequence[index]);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn next_note(&mut self) -> Option<(u8, f64)> {
+        if self.sequence.is_empty() {
+            return None;
+        }
+
+        if self.settings.mode == ArpMode::Random {
+            self.index = self.rng.gen_range(0..self.sequence.len());
+        } else if self.index >= self.sequence.len() {
+            self.index = 0;
+        }
+
+        let note = self.sequence[self.index];
+        self.index += 1;
+        Some(note)
+    }
+
+    /// Advance by one sample, following the transport when it plays, or a free clock at its tempo.
+    /// Returns the note to stop, then the note to start.
+    pub fn tick(&mut self, transport: &Transport) -> [Option<ArpEvent>; 2] {
+        let position = if transport.is_playing() {
+            transport.beat_position()
+        } else {
+            if self.sequence.is_empty() {
+                self.free_position = 0.0;
+            } else {
+                self.free_position += 1.0 / transport.samples_per_beat();
+            }
+            self.free_position
+        };
+
+        let mut events = [None, None];
+        if let Some(note) = self.current {
+            if position >= self.off_at || self.sequence.is_empty() {
+                events[0] = Some(ArpEvent::NoteOff(note));
+                self.current = None;
+            }
+        }
+
+        if self.sequence.is_empty() {
+            self.last_step = None;
+            return events;
+        }
+
+        let rate = self.settings.rate.max(1.0 / 64.0);
+        let step = (position / rate).floor() as i64;
+        if self.last_step != Some(step) {
+            self.last_step = Some(step);
+            if let Some(note) = self.current.take() {
+                events[0] = Some(ArpEvent::NoteOff(note));
+            }
+
+            if let Some((note, velocity)) = self.next_note() {
+                events[1] = Some(ArpEvent::NoteOn(note, velocity));
+                self.current = Some(note);
+                self.off_at = step as f64 * rate + rate * self.settings.gate.clamp(0.01, 1.0);
+            }
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arpeggiator(mode: ArpMode, octaves: u32, latch: bool, notes: &[u8]) -> Arpeggiator {
+        let mut arp = Arpeggiator::new(ArpSettings {
+            mode,
+            octaves,
+            latch,
+            ..Default::default()
+        });
+        for note in notes {
+            arp.note_on(*note, 1.0);
+        }
+        arp
+    }
+
+    /// Notes started in the next `steps` steps, on a free clock of 16 samples per beat.
+    fn played(arp: &mut Arpeggiator, steps: usize) -> Vec<u8> {
+        let transport = Transport::new(60.0, 16.0);
+        (0..steps * 4)
+            .filter_map(|_| match arp.tick(&transport) {
+                [_, Some(ArpEvent::NoteOn(note, _))] => Some(note),
+                _ => None,
+            })
+            .take(steps)
+            .collect()
+    }
+
+    #[test]
+    fn note_order() {
+        let chord = [64, 60, 67];
+        let order =
+            |mode, octaves, steps| played(&mut arpeggiator(mode, octaves, false, &chord), steps);
+        assert_eq!(order(ArpMode::Up, 1, 4), [60, 64, 67, 60]);
+        assert_eq!(order(ArpMode::Down, 1, 4), [67, 64, 60, 67]);
+        assert_eq!(order(ArpMode::UpDown, 1, 6), [60, 64, 67, 64, 60, 64]);
+        assert_eq!(order(ArpMode::AsPlayed, 1, 4), [64, 60, 67, 64]);
+        assert_eq!(order(ArpMode::Up, 2, 6), [60, 64, 67, 72, 76, 79]);
+        assert_eq!(order(ArpMode::Down, 2, 2), [79, 76]);
+
+        let random = order(ArpMode::Random, 1, 32);
+        assert_eq!(random.len(), 32);
+        assert!(random.iter().all(|note| chord.contains(note)));
+    }
+
+    #[test]
+    fn releasing_keys_stops_notes() {
+        let mut arp = arpeggiator(ArpMode::Up, 1, false, &[60, 64]);
+        assert_eq!(played(&mut arp, 2), [60, 64]);
+        arp.note_off(60);
+        assert_eq!(played(&mut arp, 2), [64, 64]);
+        arp.note_off(64);
+        assert!(played(&mut arp, 2).is_empty());
+    }
+
+    #[test]
+    fn latch_keeps_the_last_chord() {
+        let mut arp = arpeggiator(ArpMode::Up, 1, true, &[60, 64]);
+        arp.note_off(60);
+        arp.note_off(64);
+        assert_eq!(played(&mut arp, 3), [60, 64, 60]);
+
+        // A new chord replaces the latched one
+        arp.note_on(67, 1.0);
+        arp.note_on(69, 1.0);
+        arp.note_off(67);
+        let mut notes = played(&mut arp, 2);
+        notes.sort_unstable();
+        assert_eq!(notes, [67, 69]);
+
+        // Turning latch off keeps the held keys only
+        arp.set_settings(ArpSettings {
+            latch: false,
+            ..arp.settings()
+        });
+        assert_eq!(played(&mut arp, 2), [69, 69]);
+    }
+}
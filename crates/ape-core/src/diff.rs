@@ -0,0 +1,221 @@
+use color_eyre::eyre::{self, eyre};
+use rustfft::{num_complex::Complex, FftPlanner};
+
+use crate::{player::AudioFile, signal::amplitude_to_db};
+
+#[derive(Debug, Clone, Default)]
+pub struct DiffOptions {
+    /// Largest latency searched between the files, in frames, or `None` to compare them as they
+    /// are.
+    pub max_latency: Option<usize>,
+    /// Differences up to this amplitude are ignored when looking for the first difference.
+    pub tolerance: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct AudioDiff {
+    pub sample_rate: u32,
+    /// Frames the second file is ahead of the first one, or behind when negative.
+    pub offset: isize,
+    /// Frame of the first file where the residual starts, before its start when the second file
+    /// begins earlier once aligned.
+    pub start: isize,
+    /// Difference of the second file from the first one, missing frames counting as silence.
+    pub residual: Vec<[f32; 2]>,
+    /// Largest difference amplitude.
+    pub max: f32,
+    /// RMS amplitude of the difference, over both channels.
+    pub rms: f64,
+    /// Frame of the first file and channel of the first difference above the tolerance.
+    pub first_difference: Option<(isize, usize)>,
+}
+
+impl AudioDiff {
+    /// Largest difference, in dBFS.
+    pub fn max_db(&self) -> f64 {
+        amplitude_to_db(self.max as f64)
+    }
+
+    /// RMS difference, in dBFS.
+    pub fn rms_db(&self) -> f64 {
+        amplitude_to_db(self.rms)
+    }
+}
+
+/// Offset of `b` from `a` within `max_latency` frames, where their cross-correlation peaks.
+fn find_offset(a: &[[f32; 2]], b: &[[f32; 2]], max_latency: usize) -> isize {
+    // Nothing to correlate, and the lag range below needs two frames
+    if a.is_empty() || b.is_empty() {
+        return 0;
+    }
+    let size = (a.len() + b.len()).next_power_of_two();
+    let spectrum = |frames: &[[f32; 2]]| {
+        let mut buffer: Vec<Complex<f64>> = frames
+            .iter()
+            .map(|[left, right]| Complex::new(*left as f64 + *right as f64, 0.0))
+            .collect();
+        buffer.resize(size, Complex::default());
+        FftPlanner::new()
+            .plan_fft_forward(size)
+            .process(&mut buffer);
+        buffer
+    };
+
+    // Index `k` of the correlation holds the sum of a[n] * b[n + k], negative lags wrapping around
+    let mut correlation: Vec<_> = spectrum(a)
+        .iter()
+        .zip(spectrum(b))
+        .map(|(a, b)| a.conj() * b)
+        .collect();
+    FftPlanner::new()
+        .plan_fft_inverse(size)
+        .process(&mut correlation);
+
+    let max_latency = max_latency.min(size / 2 - 1) as isize;
+    (-max_latency..=max_latency)
+        .max_by(|x, y| {
+            let value = |lag: isize| correlation[lag.rem_euclid(size as isize) as usize].re;
+            // Smaller lags win ties, silent files staying aligned
+            value(*x)
+                .total_cmp(&value(*y))
+                .then_with(|| y.abs().cmp(&x.abs()))
+        })
+        .unwrap_or(0)
+}
+
+/// Compare two files of the same sample rate, subtracting the first one from the second one.
+pub fn diff(a: &AudioFile, b: &AudioFile, options: &DiffOptions) -> eyre::Result<AudioDiff> {
+    if a.sample_rate != b.sample_rate {
+        return Err(eyre!(
+            "the files have different sample rates ({} and {} Hz)",
+            a.sample_rate,
+            b.sample_rate
+        ));
+    }
+
+    let offset = match options.max_latency {
+        Some(max_latency) => find_offset(&a.frames, &b.frames, max_latency),
+        None => 0,
+    };
+
+    // Frames of `b` start at `-offset` once aligned on `a`
+    let start = (-offset).min(0);
+    let end = (a.frames.len() as isize).max(b.frames.len() as isize - offset);
+    let frame = |frames: &[[f32; 2]], index: isize| {
+        usize::try_from(index)
+            .ok()
+            .and_then(|index| frames.get(index).copied())
+            .unwrap_or([0.0; 2])
+    };
+
+    let residual: Vec<[f32; 2]> = (start..end)
+        .map(|index| {
+            let [a_left, a_right] = frame(&a.frames, index);
+            let [b_left, b_right] = frame(&b.frames, index + offset);
+            [b_left - a_left, b_right - a_right]
+        })
+        .collect();
+
+    let max = residual
+        .iter()
+        .flatten()
+        .fold(0.0_f32, |max, x| max.max(x.abs()));
+    let power: f64 = residual.iter().flatten().map(|x| (*x as f64).powi(2)).sum();
+    let rms = (power / (residual.len() * 2).max(1) as f64).sqrt();
+    let first_difference = residual.iter().enumerate().find_map(|(index, frame)| {
+        let channel = frame.iter().position(|x| x.abs() > options.tolerance)?;
+        Some((start + index as isize, channel))
+    });
+
+    Ok(AudioDiff {
+        sample_rate: a.sample_rate,
+        offset,
+        start,
+        residual,
+        max,
+        rms,
+        first_difference,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(frames: Vec<[f32; 2]>) -> AudioFile {
+        AudioFile {
+            frames,
+            sample_rate: 48_000,
+        }
+    }
+
+    /// Deterministic noise-like frames, correlating only with themselves.
+    fn signal(len: usize) -> Vec<[f32; 2]> {
+        (0..len)
+            .map(|index| {
+                let value = ((index * 7_919 % 1_009) as f32 / 1_009.0) - 0.5;
+                [value, value * 0.5]
+            })
+            .collect()
+    }
+
+    fn options(max_latency: Option<usize>) -> DiffOptions {
+        DiffOptions {
+            max_latency,
+            tolerance: 0.0,
+        }
+    }
+
+    #[test]
+    fn offset_is_recovered() {
+        let a = signal(2_000);
+        let mut b = vec![[0.0; 2]; 37];
+        b.extend_from_slice(&a);
+        let result = diff(&file(a.clone()), &file(b), &options(Some(100))).unwrap();
+        assert_eq!(result.offset, 37);
+        assert_eq!(result.max, 0.0);
+
+        let b = a[25..].to_vec();
+        let result = diff(&file(a), &file(b), &options(Some(100))).unwrap();
+        assert_eq!(result.offset, -25);
+    }
+
+    #[test]
+    fn identical_files_null() {
+        let a = file(signal(1_000));
+        let result = diff(&a, &a, &options(Some(10))).unwrap();
+        assert_eq!(result.offset, 0);
+        assert_eq!(result.max, 0.0);
+        assert_eq!(result.rms, 0.0);
+        assert_eq!(result.first_difference, None);
+    }
+
+    #[test]
+    fn first_difference_respects_tolerance() {
+        let a = signal(100);
+        let mut b = a.clone();
+        b[10][1] += 0.001;
+        b[50][0] += 0.1;
+        let mut options = options(None);
+        options.tolerance = 0.01;
+        let result = diff(&file(a.clone()), &file(b.clone()), &options).unwrap();
+        assert_eq!(result.first_difference, Some((50, 0)));
+
+        options.tolerance = 0.0;
+        let result = diff(&file(a), &file(b), &options).unwrap();
+        assert_eq!(result.first_difference, Some((10, 1)));
+    }
+
+    #[test]
+    fn empty_files() {
+        let empty = file(vec![]);
+        let result = diff(&empty, &empty, &options(Some(100))).unwrap();
+        assert_eq!(result.offset, 0);
+        assert!(result.residual.is_empty());
+
+        let one = file(vec![[0.5; 2]]);
+        let result = diff(&empty, &one, &options(Some(100))).unwrap();
+        assert_eq!(result.offset, 0);
+        assert_eq!(result.first_difference, Some((0, 0)));
+    }
+}
@@ -1,18 +1,31 @@
+use std::{f64::consts::TAU, str::FromStr};
+
+use color_eyre::eyre::{self, eyre};
 use fundsp::hacker::*;
-use rand::Rng;
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::{
+    note::note_to_freq,
+    sequencer::{NoteEvent, Step},
+    voice::{Adsr, Envelope, Waveform},
+};
 
-pub fn sample_noise_fn() -> Vec<f32> {
-    let mut rng = rand::thread_rng();
-    let v = rng.gen_range(-1.0..1.0);
-    vec![v, v]
+/// Tags of the parameters exposed by the DSP chains.
+#[derive(Clone, Copy)]
+pub enum DspTag {
+    Pitch = 0,
+    Width = 1,
+    Cutoff = 2,
+    Resonance = 3,
 }
 
 pub fn build_dsp_chain(sample_rate: u32) -> Box<dyn AudioUnit64> {
-    let c = lfo(|t| {
-        let pitch = 440.0;
-        let duty = lerp11(0.01, 0.99, sin_hz(0.05 * 4.0, t));
-        (pitch, duty)
-    }) >> pulse();
+    build_dsp_chain_pitch(440.0, sample_rate)
+}
+
+pub fn build_dsp_chain_pitch(pitch: f64, sample_rate: u32) -> Box<dyn AudioUnit64> {
+    let duty = lfo(|t| lerp11(0.01, 0.99, sin_hz(0.05 * 4.0, t)));
+    let c = (tag(DspTag::Pitch as Tag, pitch) | duty) >> pulse();
 
     let mut c = c >> split::<U2>();
     c.reset(Some(sample_rate as f64));
@@ -20,14 +33,621 @@ pub fn build_dsp_chain(sample_rate: u32) -> Box<dyn AudioUnit64> {
     Box::new(c)
 }
 
-pub fn build_dsp_chain_pitch(pitch: f64, sample_rate: u32) -> Box<dyn AudioUnit64> {
-    let c = lfo(move |t| {
-        let duty = lerp11(0.01, 0.99, sin_hz(0.05 * 4.0, t));
-        (pitch, duty)
-    }) >> pulse();
+/// An effect on a stereo signal, parsed from specs like `lowpass:2000` or `delay:0.3:0.5`, where
+/// missing parameters take default values.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Effect {
+    Lowpass {
+        frequency: f64,
+        q: f64,
+    },
+    Highpass {
+        frequency: f64,
+        q: f64,
+    },
+    Bandpass {
+        frequency: f64,
+        q: f64,
+    },
+    Notch {
+        frequency: f64,
+        q: f64,
+    },
+    Moog {
+        frequency: f64,
+        q: f64,
+    },
+    /// Echoes every `time` seconds, each one `feedback` times the previous one.
+    Delay {
+        time: f64,
+        feedback: f64,
+    },
+    /// `room_size` in meters, `time` to -60 dB in seconds, `mix` of the reverberated signal.
+    Reverb {
+        room_size: f64,
+        time: f64,
+        mix: f64,
+    },
+    Chorus,
+    /// Gain in dB.
+    Gain(f64),
+    /// Hard clipping to -1..1.
+    Clip,
+}
 
-    let mut c = c >> split::<U2>();
-    c.reset(Some(sample_rate as f64));
+impl FromStr for Effect {
+    type Err = String;
 
-    Box::new(c)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let name = parts.next().unwrap_or_default().to_lowercase();
+        let values = parts
+            .map(|part| {
+                part.trim()
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|value| value.is_finite())
+                    .ok_or_else(|| format!("invalid effect parameter '{part}'"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let value = |index: usize, default: f64| values.get(index).copied().unwrap_or(default);
+
+        let effect = match name.as_str() {
+            "lowpass" => Self::Lowpass {
+                frequency: value(0, 1_000.0),
+                q: value(1, 0.7),
+            },
+            "highpass" => Self::Highpass {
+                frequency: value(0, 200.0),
+                q: value(1, 0.7),
+            },
+            "bandpass" => Self::Bandpass {
+                frequency: value(0, 1_000.0),
+                q: value(1, 1.0),
+            },
+            "notch" => Self::Notch {
+                frequency: value(0, 1_000.0),
+                q: value(1, 1.0),
+            },
+            "moog" => Self::Moog {
+                frequency: value(0, 1_000.0),
+                q: value(1, 0.5),
+            },
+            "delay" => Self::Delay {
+                time: value(0, 0.25),
+                feedback: value(1, 0.4),
+            },
+            "reverb" => Self::Reverb {
+                room_size: value(0, 10.0),
+                time: value(1, 2.0),
+                mix: value(2, 0.2),
+            },
+            "chorus" => Self::Chorus,
+            "gain" => Self::Gain(value(0, 0.0)),
+            "clip" => Self::Clip,
+            other => return Err(format!("unknown effect '{other}'")),
+        };
+
+        let expected = match effect {
+            Self::Chorus | Self::Clip => 0,
+            Self::Gain(_) => 1,
+            Self::Reverb { .. } => 3,
+            _ => 2,
+        };
+        if values.len() > expected {
+            return Err(format!("too many parameters for effect '{name}'"));
+        }
+        match effect {
+            Self::Moog { frequency, q } if frequency <= 0.0 || !(0.0..=1.0).contains(&q) => {
+                Err("moog needs a positive frequency and a q from 0 to 1".into())
+            }
+            Self::Moog { .. } => Ok(effect),
+            Self::Lowpass { frequency, q }
+            | Self::Highpass { frequency, q }
+            | Self::Bandpass { frequency, q }
+            | Self::Notch { frequency, q }
+                if frequency <= 0.0 || q <= 0.0 =>
+            {
+                Err(format!("{name} needs a positive frequency and q"))
+            }
+            Self::Delay { time, feedback } if time <= 0.0 || feedback.abs() >= 1.0 => {
+                Err("delay needs a positive time and a feedback under 1".into())
+            }
+            Self::Reverb {
+                room_size,
+                time,
+                mix,
+            } if room_size <= 0.0 || time <= 0.0 || !(0.0..=1.0).contains(&mix) => {
+                Err("reverb needs a positive room size and time, and a mix from 0 to 1".into())
+            }
+            _ => Ok(effect),
+        }
+    }
+}
+
+/// The same mono unit on both channels, built from the channel index.
+fn per_channel(unit: impl Fn(i64) -> Box<dyn AudioUnit64>) -> Net64 {
+    Net64::stack_op(Net64::wrap(unit(0)), Net64::wrap(unit(1)))
+}
+
+impl Effect {
+    fn build(&self) -> Net64 {
+        match *self {
+            Self::Lowpass { frequency, q } => per_channel(|_| Box::new(lowpass_hz(frequency, q))),
+            Self::Highpass { frequency, q } => per_channel(|_| Box::new(highpass_hz(frequency, q))),
+            Self::Bandpass { frequency, q } => per_channel(|_| Box::new(bandpass_hz(frequency, q))),
+            Self::Notch { frequency, q } => per_channel(|_| Box::new(notch_hz(frequency, q))),
+            Self::Moog { frequency, q } => per_channel(|_| Box::new(moog_hz(frequency, q))),
+            Self::Delay {
+                time,
+                feedback: amount,
+            } => per_channel(|_| Box::new(pass() & feedback(delay(time) * amount))),
+            Self::Reverb {
+                room_size,
+                time,
+                mix,
+            } => Net64::wrap(Box::new(
+                (multipass::<U2>() * (1.0 - mix)) & (reverb_stereo(room_size, time) * mix),
+            )),
+            Self::Chorus => per_channel(|channel| Box::new(chorus(channel, 0.015, 0.005, 0.3))),
+            Self::Gain(db) => Net64::wrap(Box::new(multipass::<U2>() * db_amp(db))),
+            Self::Clip => per_channel(|_| Box::new(clip())),
+        }
+    }
+
+    /// Filter frequency, in Hz.
+    fn frequency(&self) -> Option<f64> {
+        match *self {
+            Self::Lowpass { frequency, .. }
+            | Self::Highpass { frequency, .. }
+            | Self::Bandpass { frequency, .. }
+            | Self::Notch { frequency, .. }
+            | Self::Moog { frequency, .. } => Some(frequency),
+            _ => None,
+        }
+    }
+
+    /// Time the effect keeps sounding once its input stops, until -60 dB, in seconds.
+    pub fn tail(&self) -> f64 {
+        match *self {
+            // Echo `n` is `feedback^n` times the input, `n * time` seconds later
+            Self::Delay { time, feedback } if feedback != 0.0 => {
+                time * (-3.0 / feedback.abs().log10()).ceil()
+            }
+            Self::Reverb { time, .. } => time,
+            _ => 0.0,
+        }
+    }
+}
+
+/// Time a chain of `effects` keeps sounding once its input stops, in seconds.
+pub fn effect_chain_tail(effects: &[Effect]) -> f64 {
+    effects.iter().map(Effect::tail).sum()
+}
+
+/// A stereo unit applying `effects` in order, passing the signal through when there are none.
+/// Filter frequencies must be below the Nyquist frequency.
+pub fn build_effect_chain(
+    effects: &[Effect],
+    sample_rate: u32,
+) -> eyre::Result<Box<dyn AudioUnit64>> {
+    let nyquist = sample_rate as f64 / 2.0;
+    if let Some(frequency) = effects
+        .iter()
+        .filter_map(Effect::frequency)
+        .find(|frequency| *frequency >= nyquist)
+    {
+        return Err(eyre!(
+            "the filter frequency {frequency} Hz is not below the Nyquist frequency ({nyquist} Hz)"
+        ));
+    }
+
+    let mut chain = effects
+        .iter()
+        .fold(Net64::wrap(Box::new(multipass::<U2>())), |chain, effect| {
+            Net64::pipe_op(chain, effect.build())
+        });
+    chain.reset(Some(sample_rate as f64));
+
+    Ok(Box::new(chain))
+}
+
+/// Parameter modulated by the LFO of `DspSynth`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LfoTarget {
+    /// Pulse width, the depth being the fraction of the whole range.
+    #[default]
+    Width,
+    /// Pitch, the depth being in semitones.
+    Pitch,
+    /// Filter cutoff, the depth being in octaves.
+    Cutoff,
+    /// Volume, the depth being the fraction removed at the lowest point.
+    Volume,
+}
+
+impl FromStr for LfoTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "width" | "pwm" => Ok(Self::Width),
+            "pitch" => Ok(Self::Pitch),
+            "cutoff" => Ok(Self::Cutoff),
+            "volume" => Ok(Self::Volume),
+            other => Err(format!("unknown LFO target '{other}'")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterMode {
+    Lowpass,
+    Highpass,
+    Bandpass,
+    Moog,
+}
+
+impl FromStr for FilterMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "lowpass" => Ok(Self::Lowpass),
+            "highpass" => Ok(Self::Highpass),
+            "bandpass" => Ok(Self::Bandpass),
+            "moog" => Ok(Self::Moog),
+            other => Err(format!("unknown filter '{other}'")),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DspOptions {
+    pub waveform: Waveform,
+    /// Frequency played when there are no steps, in Hz.
+    pub frequency: f64,
+    /// LFO rate, in Hz.
+    pub lfo_rate: f64,
+    pub lfo_depth: f64,
+    pub lfo_target: LfoTarget,
+    pub filter: Option<FilterMode>,
+    /// Filter cutoff, in Hz.
+    pub cutoff: f64,
+    /// Filter Q, from 0 to 1 for the moog filter.
+    pub resonance: f64,
+    pub adsr: Adsr,
+    pub volume: f64,
+    /// Steps played in a loop, or a held note when empty.
+    pub steps: Vec<Option<Step>>,
+    /// Step length, in seconds.
+    pub step_duration: f64,
+    /// Seed of the step probabilities, taken from entropy when missing.
+    pub seed: Option<u64>,
+}
+
+/// Defaults to the pulse of `build_dsp_chain`, its width swept by the LFO.
+impl Default for DspOptions {
+    fn default() -> Self {
+        Self {
+            waveform: Waveform::Pulse,
+            frequency: 440.0,
+            lfo_rate: 0.2,
+            lfo_depth: 1.0,
+            lfo_target: LfoTarget::Width,
+            filter: None,
+            cutoff: 2_000.0,
+            resonance: 0.7,
+            adsr: Adsr::default(),
+            volume: 1.0,
+            steps: vec![],
+            step_duration: 0.125,
+            seed: None,
+        }
+    }
+}
+
+/// A monophonic synth: an oscillator through an optional filter, shaped by an envelope and
+/// modulated by a sine LFO, playing a held note or a loop of steps.
+pub struct DspSynth {
+    options: DspOptions,
+    oscillator: Box<dyn AudioUnit64>,
+    filter: Option<Box<dyn AudioUnit64>>,
+    envelope: Envelope,
+    sample_rate: f64,
+    frame: u64,
+    /// Id of the note being played from the steps, with its frequency and velocity.
+    note_id: Option<u64>,
+    frequency: f64,
+    velocity: f64,
+    step_index: usize,
+    /// Events to send, at positions in frames.
+    pending: Vec<(f64, NoteEvent)>,
+    next_id: u64,
+    rng: StdRng,
+}
+
+impl DspSynth {
+    pub fn new(options: DspOptions, sample_rate: u32) -> eyre::Result<Self> {
+        let sample_rate = sample_rate as f64;
+        let step_frames = options.step_duration * sample_rate;
+        if step_frames.is_nan() || step_frames < 1.0 {
+            return Err(eyre!("steps must last at least one sample"));
+        }
+        let pitch = || tag(DspTag::Pitch as Tag, options.frequency);
+        let mut oscillator: Box<dyn AudioUnit64> = match options.waveform {
+            Waveform::Sine => Box::new(pitch() >> sine()),
+            Waveform::Saw => Box::new(pitch() >> saw()),
+            Waveform::Square => Box::new(pitch() >> square()),
+            Waveform::Triangle => Box::new(pitch() >> triangle()),
+            Waveform::Pulse => Box::new((pitch() | tag(DspTag::Width as Tag, 0.5)) >> pulse()),
+        };
+        oscillator.reset(Some(sample_rate));
+
+        let filter = options.filter.map(|mode| {
+            let inputs = || {
+                pass()
+                    | tag(DspTag::Cutoff as Tag, options.cutoff)
+                    | tag(DspTag::Resonance as Tag, options.resonance)
+            };
+            let mut filter: Box<dyn AudioUnit64> = match mode {
+                FilterMode::Lowpass => Box::new(inputs() >> lowpass()),
+                FilterMode::Highpass => Box::new(inputs() >> highpass()),
+                FilterMode::Bandpass => Box::new(inputs() >> bandpass()),
+                FilterMode::Moog => Box::new(inputs() >> moog()),
+            };
+            filter.reset(Some(sample_rate));
+            filter
+        });
+
+        let mut envelope = Envelope::new(options.adsr, sample_rate);
+        if options.steps.is_empty() {
+            envelope.gate_on();
+        }
+        let rng = match options.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+
+        Ok(Self {
+            frequency: options.frequency,
+            options,
+            oscillator,
+            filter,
+            envelope,
+            sample_rate,
+            frame: 0,
+            note_id: None,
+            velocity: 1.0,
+            step_index: 0,
+            pending: Vec::with_capacity(16),
+            next_id: 0,
+            rng,
+        })
+    }
+
+    /// Schedule the steps starting at the current frame.
+    fn schedule(&mut self) {
+        let step_frames = self.options.step_duration * self.sample_rate;
+        loop {
+            let start = self.step_index as f64 * step_frames;
+            if start > self.frame as f64 {
+                return;
+            }
+
+            let steps = &self.options.steps;
+            if let Some(step) = &steps[self.step_index % steps.len()] {
+                self.pending
+                    .extend(step.events(start, step_frames, self.next_id, &mut self.rng));
+                self.next_id += step.ratchets as u64;
+            }
+
+            self.step_index += 1;
+        }
+    }
+
+    /// Send the events due, offs first to retrigger repeated notes.
+    fn send_events(&mut self) {
+        let now = self.frame as f64;
+        for on in [false, true] {
+            let mut index = 0;
+            while index < self.pending.len() {
+                let (position, event) = self.pending[index];
+                if position > now || matches!(event, NoteEvent::On(..)) != on {
+                    index += 1;
+                    continue;
+                }
+
+                self.pending.swap_remove(index);
+                match event {
+                    NoteEvent::On(id, note, velocity) => {
+                        self.note_id = Some(id);
+                        self.frequency = note_to_freq(note);
+                        self.velocity = velocity;
+                        self.envelope.gate_on();
+                    }
+                    NoteEvent::Off(id) => {
+                        if self.note_id == Some(id) {
+                            self.envelope.gate_off();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn tick(&mut self) -> [f32; 2] {
+        if !self.options.steps.is_empty() {
+            self.schedule();
+            self.send_events();
+        }
+
+        let time = self.frame as f64 / self.sample_rate;
+        let sine = (TAU * self.options.lfo_rate * time).sin();
+        let depth = self.options.lfo_depth;
+        let mut frequency = self.frequency;
+        let mut cutoff = self.options.cutoff;
+        let mut volume = self.options.volume * self.velocity;
+        match self.options.lfo_target {
+            LfoTarget::Width => {
+                let width = 0.5 + 0.49 * (sine * depth).clamp(-1.0, 1.0);
+                self.oscillator.set(DspTag::Width as Tag, width);
+            }
+            LfoTarget::Pitch => frequency *= (sine * depth / 12.0).exp2(),
+            LfoTarget::Cutoff => cutoff *= (sine * depth).exp2(),
+            LfoTarget::Volume => volume *= 1.0 - depth.clamp(0.0, 1.0) * (0.5 - 0.5 * sine),
+        }
+        self.oscillator.set(DspTag::Pitch as Tag, frequency);
+
+        let mut value = self.oscillator.get_mono();
+        if let Some(filter) = &mut self.filter {
+            let nyquist = self.sample_rate * 0.49;
+            filter.set(DspTag::Cutoff as Tag, cutoff.clamp(10.0, nyquist));
+            value = filter.filter_mono(value);
+        }
+        value *= self.envelope.tick() * volume;
+
+        self.frame += 1;
+        [value as f32, value as f32]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn effect_tails() {
+        let effects: Vec<Effect> = ["lowpass", "delay:0.5:0.5", "reverb:10:3"]
+            .iter()
+            .map(|spec| spec.parse().unwrap())
+            .collect();
+        // 0.5^10 is the first echo under -60 dB
+        assert_eq!(effects[1].tail(), 5.0);
+        assert_eq!(effect_chain_tail(&effects), 8.0);
+        assert_eq!("delay:0.5:0".parse::<Effect>().unwrap().tail(), 0.0);
+    }
+
+    #[test]
+    fn effect_specs() {
+        assert_eq!(
+            "lowpass".parse(),
+            Ok(Effect::Lowpass {
+                frequency: 1_000.0,
+                q: 0.7
+            })
+        );
+        assert_eq!(
+            "Moog:800:0.5".parse(),
+            Ok(Effect::Moog {
+                frequency: 800.0,
+                q: 0.5
+            })
+        );
+        assert_eq!(
+            "reverb:20".parse(),
+            Ok(Effect::Reverb {
+                room_size: 20.0,
+                time: 2.0,
+                mix: 0.2
+            })
+        );
+        assert_eq!("gain:-6".parse(), Ok(Effect::Gain(-6.0)));
+        assert_eq!("clip".parse(), Ok(Effect::Clip));
+    }
+
+    #[test]
+    fn invalid_effect_specs() {
+        for spec in [
+            "flanger",
+            "clip:1",
+            "gain:3:4",
+            "lowpass:abc",
+            "lowpass:0",
+            "highpass:-100",
+            "bandpass:nan",
+            "notch:1000:0",
+            "lowpass:1000:-1",
+            "moog:1000:1.5",
+            "delay:0",
+            "delay:0.3:1",
+            "delay:0.3:nan",
+            "delay:inf",
+            "reverb:0",
+            "reverb:-10",
+            "reverb:10:0",
+            "reverb:10:2:1.5",
+            "gain:inf",
+        ] {
+            assert!(spec.parse::<Effect>().is_err(), "{spec}");
+        }
+    }
+
+    #[test]
+    fn filters_must_be_below_nyquist() {
+        let lowpass = |frequency| Effect::Lowpass { frequency, q: 0.7 };
+        assert!(build_effect_chain(&[lowpass(20_000.0)], 44_100).is_ok());
+        assert!(build_effect_chain(&[Effect::Clip, lowpass(22_050.0)], 44_100).is_err());
+    }
+
+    fn synth(steps: &[&str]) -> DspSynth {
+        let options = DspOptions {
+            waveform: Waveform::Sine,
+            lfo_depth: 0.0,
+            adsr: Adsr {
+                release: 0.01,
+                ..Default::default()
+            },
+            steps: steps.iter().map(|step| step.parse().ok()).collect(),
+            step_duration: 0.1,
+            seed: Some(1),
+            ..Default::default()
+        };
+        DspSynth::new(options, 8_000).unwrap()
+    }
+
+    /// Peak level of the next `frames` frames.
+    fn peak(synth: &mut DspSynth, frames: usize) -> f32 {
+        (0..frames).fold(0.0, |peak, _| peak.max(synth.tick()[0].abs()))
+    }
+
+    #[test]
+    fn held_note() {
+        let mut synth = synth(&[]);
+        assert!(peak(&mut synth, 800) > 0.5);
+        assert_eq!(synth.frequency, 440.0);
+        assert!(peak(&mut synth, 8_000) > 0.5);
+    }
+
+    #[test]
+    fn steps_play_in_a_loop() {
+        // 800 frames per step, the note lasting half of it and releasing in 80 frames
+        let mut synth = synth(&["A5", "-"]);
+        assert!(peak(&mut synth, 400) > 0.5);
+        assert_eq!(synth.frequency, 880.0);
+        peak(&mut synth, 100);
+        assert_eq!(peak(&mut synth, 1_100), 0.0);
+        assert!(peak(&mut synth, 400) > 0.5);
+    }
+
+    #[test]
+    fn seeded_steps_are_reproducible() {
+        let render = || {
+            let mut synth = synth(&["A4:p0.5", "C5:p0.5"]);
+            (0..16_000).map(|_| synth.tick()[0]).collect::<Vec<_>>()
+        };
+        assert_eq!(render(), render());
+    }
+
+    #[test]
+    fn steps_last_a_sample() {
+        for step_duration in [0.0, 1e-5, f64::NAN] {
+            let options = DspOptions {
+                steps: vec![None],
+                step_duration,
+                ..Default::default()
+            };
+            assert!(DspSynth::new(options, 8_000).is_err(), "{step_duration}");
+        }
+    }
 }
@@ -11,7 +11,7 @@ pub struct SampleRequestOptions {
 pub fn stream_setup_for_device(
     device: cpal::Device,
     config: cpal::SupportedStreamConfig,
-    on_sample: impl FnMut() -> Vec<f32> + Send + 'static,
+    on_sample: impl FnMut() -> [f32; 2] + Send + 'static,
 ) -> eyre::Result<cpal::Stream> {
     match config.sample_format() {
         cpal::SampleFormat::F32 => stream_make::<f32>(&device, &config.into(), on_sample),
@@ -43,7 +43,7 @@ pub fn host_device_setup() -> eyre::Result<(cpal::Host, cpal::Device, cpal::Supp
 pub fn stream_make<T>(
     device: &cpal::Device,
     config: &cpal::StreamConfig,
-    mut on_sample: impl FnMut() -> Vec<f32> + Send + 'static,
+    mut on_sample: impl FnMut() -> [f32; 2] + Send + 'static,
 ) -> eyre::Result<cpal::Stream>
 where
     T: cpal::Sample,
@@ -77,7 +77,7 @@ where
 fn on_window<T>(
     output: &mut [T],
     request: &mut SampleRequestOptions,
-    mut on_sample: impl FnMut() -> Vec<f32>,
+    mut on_sample: impl FnMut() -> [f32; 2],
 ) where
     T: cpal::Sample,
 {
@@ -6,15 +6,33 @@ pub fn export_to_wav(
     path: &Path,
     spec: hound::WavSpec,
     duration: usize,
-    mut sample_fn: impl FnMut() -> Vec<f32>,
+    sample_fn: impl FnMut() -> [f32; 2],
+) -> eyre::Result<()> {
+    let frames = spec.sample_rate as usize * duration;
+    export_frames_to_wav(path, spec, frames, sample_fn)
+}
+
+/// Export an exact number of frames.
+pub fn export_frames_to_wav(
+    path: &Path,
+    spec: hound::WavSpec,
+    frames: usize,
+    mut sample_fn: impl FnMut() -> [f32; 2],
 ) -> eyre::Result<()> {
     let mut writer = hound::WavWriter::create(path, spec)?;
-    for _ in 0..(spec.sample_rate * duration as u32) {
+    for _ in 0..frames {
         let samples = sample_fn();
         for sample in samples {
+            if spec.sample_format == hound::SampleFormat::Float {
+                writer.write_sample(sample)?;
+                continue;
+            }
+            // Full scale samples must fit in the integer range
+            let sample = sample.clamp(-1.0, 1.0);
             let value = match spec.bits_per_sample {
-                16 => (sample * 32768.0) as i16,
-                8 => (sample * 127.0) as i16,
+                24 => (sample * 8_388_607.0) as i32,
+                16 => (sample * 32767.0) as i16 as i32,
+                8 => (sample * 127.0) as i16 as i32,
                 _ => panic!("Oops"),
             };
             writer.write_sample(value)?;
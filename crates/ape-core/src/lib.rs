@@ -1,6 +1,17 @@
+pub mod arp;
+pub mod diff;
 pub mod dsp;
 pub mod engine;
 pub mod export;
+pub mod measure;
+pub mod noise;
+pub mod note;
+pub mod params;
+pub mod player;
+pub mod sequencer;
+pub mod signal;
+pub mod transport;
+pub mod voice;
 
 use std::{
     path::PathBuf,
@@ -55,7 +66,7 @@ pub struct DirectOutput {
 fn stream_loop(
     device: cpal::Device,
     config: cpal::SupportedStreamConfig,
-    sample_fn: impl FnMut() -> Vec<f32> + Send + 'static,
+    sample_fn: impl FnMut() -> [f32; 2] + Send + 'static,
 ) -> eyre::Result<()> {
     let stream = stream_setup_for_device(device, config, sample_fn)?;
     stream.play()?;
@@ -76,7 +87,7 @@ fn stream_loop(
 fn stream_loop_spinlock(
     device: cpal::Device,
     config: cpal::SupportedStreamConfig,
-    sample_fn: impl FnMut() -> Vec<f32> + Send + 'static,
+    sample_fn: impl FnMut() -> [f32; 2] + Send + 'static,
     running: Arc<AtomicBool>,
 ) -> eyre::Result<()> {
     let stream = stream_setup_for_device(device, config, sample_fn)?;
@@ -91,7 +102,7 @@ fn stream_loop_spinlock(
 
 pub fn process_stream(
     output: AudioOutput,
-    sample_fn: impl FnMut() -> Vec<f32> + Send + 'static,
+    sample_fn: impl FnMut() -> [f32; 2] + Send + 'static,
 ) -> eyre::Result<()> {
     match output {
         AudioOutput::Wav(params) => {
@@ -103,7 +114,7 @@ pub fn process_stream(
 
 pub fn start_stream_thread(
     output: AudioOutput,
-    sample_fn: impl FnMut() -> Vec<f32> + Send + 'static,
+    sample_fn: impl FnMut() -> [f32; 2] + Send + 'static,
     running: Arc<AtomicBool>,
 ) -> eyre::Result<JoinHandle<()>> {
     Ok(std::thread::spawn(|| match output {
@@ -0,0 +1,394 @@
+use std::{f64::consts::TAU, str::FromStr};
+
+use color_eyre::eyre::{self, eyre};
+use fundsp::hacker::AudioUnit64;
+use rustfft::{num_complex::Complex, FftPlanner};
+
+use crate::signal::{amplitude_to_db, db_to_amplitude, sweep_phase};
+
+/// Harmonics measured for the THD, the fundamental being the first one.
+const HARMONICS: usize = 10;
+/// Length of the THD analysis, in seconds.
+const THD_DURATION: f64 = 0.5;
+/// Regularization of the sweep deconvolution, relative to the sweep spectrum peak.
+const REGULARIZATION: f64 = 1e-6;
+/// Samples before the impulse response kept for the frequency response, the deconvolution
+/// spreading the impulse on both sides.
+const PRE_ROLL: usize = 2_048;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Stimulus {
+    /// Exponential sine sweep, deconvolved into the impulse response.
+    #[default]
+    Sweep,
+    Impulse,
+}
+
+impl FromStr for Stimulus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "sweep" => Ok(Self::Sweep),
+            "impulse" => Ok(Self::Impulse),
+            other => Err(format!("unknown stimulus '{other}'")),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MeasureOptions {
+    pub stimulus: Stimulus,
+    pub sample_rate: u32,
+    /// Frequency range of the response, in Hz, the sweep going from an octave below to the
+    /// Nyquist frequency.
+    pub start: f64,
+    pub end: f64,
+    /// Sweep duration, in seconds.
+    pub sweep_duration: f64,
+    /// Impulse response length, in samples.
+    pub ir_length: usize,
+    /// Level of the stimuli, in dBFS.
+    pub level: f64,
+    /// Frequency response points per octave.
+    pub points_per_octave: usize,
+    /// Fundamentals of the THD measurements, in Hz.
+    pub thd_frequencies: Vec<f64>,
+    /// Output measured, every input getting the stimulus.
+    pub channel: usize,
+}
+
+impl Default for MeasureOptions {
+    fn default() -> Self {
+        Self {
+            stimulus: Stimulus::Sweep,
+            sample_rate: 44_100,
+            start: 20.0,
+            end: 20_000.0,
+            sweep_duration: 2.0,
+            ir_length: 8_192,
+            level: -6.0,
+            points_per_octave: 12,
+            thd_frequencies: vec![1_000.0],
+            channel: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResponsePoint {
+    pub frequency: f64,
+    /// Gain, in dB.
+    pub magnitude: f64,
+    /// Phase, in degrees from -180 to 180.
+    pub phase: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distortion {
+    pub frequency: f64,
+    /// Ratio of the harmonics to the fundamental, or `None` when the fundamental doesn't pass.
+    pub thd: Option<f64>,
+    /// Levels of the harmonics below the Nyquist frequency from the second one, relative to the
+    /// fundamental, in dB.
+    pub harmonics: Vec<f64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Measurement {
+    pub sample_rate: u32,
+    pub impulse_response: Vec<f64>,
+    pub response: Vec<ResponsePoint>,
+    pub distortion: Vec<Distortion>,
+}
+
+/// Run `input` through `unit` from its initial state, followed by silence up to `frames`.
+fn process(
+    unit: &mut dyn AudioUnit64,
+    channel: usize,
+    input: &[f64],
+    frames: usize,
+    sample_rate: u32,
+) -> Vec<f64> {
+    unit.reset(Some(sample_rate as f64));
+    let mut inputs = vec![0.0; unit.inputs()];
+    let mut outputs = vec![0.0; unit.outputs()];
+
+    (0..frames)
+        .map(|frame| {
+            inputs.fill(input.get(frame).copied().unwrap_or(0.0));
+            unit.tick(&inputs, &mut outputs);
+            outputs[channel]
+        })
+        .collect()
+}
+
+/// Complex amplitude at `frequency` of `signal`, its first sample being at time `first`.
+fn dft(signal: &[f64], first: isize, frequency: f64, sample_rate: u32) -> Complex<f64> {
+    let omega = -TAU * frequency / sample_rate as f64;
+    signal
+        .iter()
+        .enumerate()
+        .map(|(n, x)| Complex::from_polar(*x, omega * (first + n as isize) as f64))
+        .sum()
+}
+
+/// Impulse response of the unit, from an exponential sweep divided out in the frequency domain,
+/// with the `PRE_ROLL` samples before it. The harmonics of the sweep end up at negative times,
+/// before the pre-roll.
+fn sweep_impulse_response(
+    unit: &mut dyn AudioUnit64,
+    options: &MeasureOptions,
+) -> (Vec<f64>, Vec<f64>) {
+    let sample_rate = options.sample_rate as f64;
+    let amplitude = db_to_amplitude(options.level);
+    let (start, end) = (options.start / 2.0, sample_rate / 2.0);
+    let sweep: Vec<f64> = (0..(options.sweep_duration * sample_rate).round() as usize)
+        .map(|frame| {
+            let time = frame as f64 / sample_rate;
+            let phase = sweep_phase(start, end, options.sweep_duration, false, time);
+            amplitude * phase.sin()
+        })
+        .collect();
+
+    let frames = sweep.len() + options.ir_length;
+    let output = process(unit, options.channel, &sweep, frames, options.sample_rate);
+
+    let size = (frames + PRE_ROLL).next_power_of_two();
+    let spectrum = |signal: &[f64]| {
+        let mut buffer: Vec<Complex<f64>> = signal.iter().map(|x| Complex::new(*x, 0.0)).collect();
+        buffer.resize(size, Complex::default());
+        FftPlanner::new()
+            .plan_fft_forward(size)
+            .process(&mut buffer);
+        buffer
+    };
+    let input_spectrum = spectrum(&sweep);
+    let mut response = spectrum(&output);
+
+    let peak = input_spectrum
+        .iter()
+        .map(|x| x.norm_sqr())
+        .fold(0.0, f64::max);
+    for (y, x) in response.iter_mut().zip(&input_spectrum) {
+        *y = *y * x.conj() / (x.norm_sqr() + REGULARIZATION * peak);
+    }
+    FftPlanner::new()
+        .plan_fft_inverse(size)
+        .process(&mut response);
+
+    let real = |values: &[Complex<f64>]| values.iter().map(|x| x.re / size as f64).collect();
+    (
+        real(&response[size - PRE_ROLL..]),
+        real(&response[..options.ir_length]),
+    )
+}
+
+fn distortion(unit: &mut dyn AudioUnit64, options: &MeasureOptions, frequency: f64) -> Distortion {
+    let sample_rate = options.sample_rate as f64;
+    let amplitude = db_to_amplitude(options.level);
+    // The response settles before the analysis
+    let settle = options.ir_length;
+    let length = (THD_DURATION * sample_rate) as usize;
+    let sine: Vec<f64> = (0..settle + length)
+        .map(|frame| amplitude * (TAU * frequency * frame as f64 / sample_rate).sin())
+        .collect();
+
+    let output = process(
+        unit,
+        options.channel,
+        &sine,
+        sine.len(),
+        options.sample_rate,
+    );
+    // A Hann window keeps the fundamental from leaking into the harmonics
+    let windowed: Vec<f64> = output[settle..]
+        .iter()
+        .enumerate()
+        .map(|(n, x)| x * (0.5 - 0.5 * (TAU * n as f64 / length as f64).cos()))
+        .collect();
+
+    let levels: Vec<f64> = (1..=HARMONICS)
+        .map(|harmonic| harmonic as f64 * frequency)
+        .filter(|harmonic| *harmonic < sample_rate / 2.0)
+        .map(|harmonic| dft(&windowed, 0, harmonic, options.sample_rate).norm())
+        .collect();
+
+    // Below -120 dB, the fundamental is considered blocked
+    let fundamental = levels[0];
+    if fundamental < 1e-6 * amplitude * length as f64 / 4.0 {
+        return Distortion {
+            frequency,
+            thd: None,
+            harmonics: vec![],
+        };
+    }
+    let harmonics = &levels[1..];
+    let power: f64 = harmonics.iter().map(|level| level * level).sum();
+    Distortion {
+        frequency,
+        thd: Some(power.sqrt() / fundamental),
+        harmonics: harmonics
+            .iter()
+            .map(|level| amplitude_to_db(level / fundamental))
+            .collect(),
+    }
+}
+
+/// Measure the impulse response, frequency response and harmonic distortion of a unit with at
+/// least one input.
+pub fn measure(unit: &mut dyn AudioUnit64, options: &MeasureOptions) -> eyre::Result<Measurement> {
+    let nyquist = options.sample_rate as f64 / 2.0;
+    if unit.inputs() == 0 || options.channel >= unit.outputs() {
+        return Err(eyre!(
+            "the unit needs an input, and an output {}",
+            options.channel
+        ));
+    }
+    if !(0.0 < options.start && options.start < options.end && options.end <= nyquist) {
+        return Err(eyre!(
+            "the frequency range must be increasing, between 0 and {nyquist} Hz"
+        ));
+    }
+    if options.ir_length == 0
+        || !(options.sweep_duration.is_finite() && options.sweep_duration > 0.0)
+        || options.points_per_octave == 0
+    {
+        return Err(eyre!(
+            "the impulse response, sweep and points per octave can't be empty"
+        ));
+    }
+    if !options.level.is_finite() {
+        return Err(eyre!("the level must be finite"));
+    }
+    if let Some(frequency) = options
+        .thd_frequencies
+        .iter()
+        .find(|frequency| !(0.0 < **frequency && **frequency < nyquist))
+    {
+        return Err(eyre!("can't measure the THD at {frequency} Hz"));
+    }
+
+    let (pre_roll, impulse_response) = match options.stimulus {
+        Stimulus::Sweep => sweep_impulse_response(unit, options),
+        Stimulus::Impulse => {
+            let amplitude = db_to_amplitude(options.level);
+            let output = process(
+                unit,
+                options.channel,
+                &[amplitude],
+                options.ir_length,
+                options.sample_rate,
+            );
+            (vec![], output.iter().map(|x| x / amplitude).collect())
+        }
+    };
+
+    let octaves = (options.end / options.start).log2();
+    let points = (octaves * options.points_per_octave as f64).floor() as usize + 1;
+    let response = (0..points)
+        .map(|point| {
+            let frequency =
+                options.start * (point as f64 / options.points_per_octave as f64).exp2();
+            let sample_rate = options.sample_rate;
+            let value = dft(
+                &pre_roll,
+                -(pre_roll.len() as isize),
+                frequency,
+                sample_rate,
+            ) + dft(&impulse_response, 0, frequency, sample_rate);
+            ResponsePoint {
+                frequency,
+                magnitude: amplitude_to_db(value.norm()),
+                phase: value.arg().to_degrees(),
+            }
+        })
+        .collect();
+
+    let distortion = options
+        .thd_frequencies
+        .iter()
+        .map(|frequency| distortion(unit, options, *frequency))
+        .collect();
+
+    Ok(Measurement {
+        sample_rate: options.sample_rate,
+        impulse_response,
+        response,
+        distortion,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsp::{build_effect_chain, Effect};
+
+    fn options() -> MeasureOptions {
+        MeasureOptions {
+            start: 250.0,
+            end: 4_000.0,
+            sweep_duration: 1.0,
+            points_per_octave: 1,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn passthrough_is_flat() {
+        let mut chain = build_effect_chain(&[], 44_100).unwrap();
+        let measurement = measure(chain.as_mut(), &options()).unwrap();
+        assert_eq!(measurement.response.len(), 5);
+        for point in &measurement.response {
+            assert!(point.magnitude.abs() < 0.01, "{point:?}");
+            assert!(point.phase.abs() < 0.1, "{point:?}");
+        }
+        let thd = measurement.distortion[0].thd.unwrap();
+        assert!(thd < 1e-6, "{thd}");
+    }
+
+    #[test]
+    fn lowpass_cutoff() {
+        let lowpass = Effect::Lowpass {
+            frequency: 1_000.0,
+            q: std::f64::consts::FRAC_1_SQRT_2,
+        };
+        let mut chain = build_effect_chain(&[lowpass], 44_100).unwrap();
+        let measurement = measure(chain.as_mut(), &options()).unwrap();
+        let magnitudes: Vec<f64> = measurement
+            .response
+            .iter()
+            .map(|point| point.magnitude)
+            .collect();
+        // 250, 500, 1000, 2000 and 4000 Hz
+        assert!(magnitudes[0].abs() < 0.1, "{magnitudes:?}");
+        assert!((magnitudes[2] + 3.0).abs() < 0.1, "{magnitudes:?}");
+        assert!(magnitudes[4] < -20.0, "{magnitudes:?}");
+    }
+
+    #[test]
+    fn invalid_options_are_rejected() {
+        let mut chain = build_effect_chain(&[], 44_100).unwrap();
+        let invalid = [
+            MeasureOptions {
+                sweep_duration: f64::NAN,
+                ..options()
+            },
+            MeasureOptions {
+                start: f64::NAN,
+                ..options()
+            },
+            MeasureOptions {
+                end: 30_000.0,
+                ..options()
+            },
+            MeasureOptions {
+                thd_frequencies: vec![f64::NAN],
+                ..options()
+            },
+        ];
+        for options in &invalid {
+            assert!(measure(chain.as_mut(), options).is_err(), "{options:?}");
+        }
+    }
+}
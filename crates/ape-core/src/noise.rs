@@ -0,0 +1,262 @@
+use std::str::FromStr;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum NoiseColor {
+    #[default]
+    White,
+    Pink,
+    Brown,
+    Blue,
+    Violet,
+    Velvet,
+}
+
+impl FromStr for NoiseColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "white" => Ok(Self::White),
+            "pink" => Ok(Self::Pink),
+            "brown" | "red" => Ok(Self::Brown),
+            "blue" => Ok(Self::Blue),
+            "violet" | "purple" => Ok(Self::Violet),
+            "velvet" => Ok(Self::Velvet),
+            other => Err(format!("unknown noise color '{other}'")),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NoiseOptions {
+    pub color: NoiseColor,
+    /// Seed used for reproducible renders, taken from entropy when missing.
+    pub seed: Option<u64>,
+    pub amplitude: f32,
+    /// 0.0 plays the same signal on both channels, 1.0 plays independent signals.
+    pub decorrelation: f32,
+    /// Impulses per second, only used by velvet noise.
+    pub density: f32,
+}
+
+impl Default for NoiseOptions {
+    fn default() -> Self {
+        Self {
+            color: NoiseColor::White,
+            seed: None,
+            amplitude: 1.0,
+            decorrelation: 0.0,
+            density: 2_000.0,
+        }
+    }
+}
+
+/// Per-channel filter state, shaping white noise into the requested color.
+#[derive(Default, Clone)]
+struct NoiseChannel {
+    pink: [f32; 7],
+    previous: f32,
+    brown: f32,
+    velvet_position: u32,
+    velvet_counter: u32,
+    velvet_sign: f32,
+}
+
+impl NoiseChannel {
+    fn next(&mut self, color: NoiseColor, velvet_period: u32, rng: &mut StdRng) -> f32 {
+        match color {
+            NoiseColor::White => rng.gen_range(-1.0..1.0),
+            NoiseColor::Pink => {
+                let white = rng.gen_range(-1.0..1.0);
+                self.next_pink(white)
+            }
+            NoiseColor::Brown => {
+                let white: f32 = rng.gen_range(-1.0..1.0);
+                self.brown = (self.brown + 0.02 * white) / 1.02;
+                (self.brown * 3.5).clamp(-1.0, 1.0)
+            }
+            NoiseColor::Blue => {
+                // Differentiated pink noise, +3 dB/octave
+                let white = rng.gen_range(-1.0..1.0);
+                let pink = self.next_pink(white);
+                let value = (pink - self.previous) * 2.0;
+                self.previous = pink;
+                value.clamp(-1.0, 1.0)
+            }
+            NoiseColor::Violet => {
+                // Differentiated white noise, +6 dB/octave
+                let white = rng.gen_range(-1.0..1.0);
+                let value = (white - self.previous) * 0.5;
+                self.previous = white;
+                value
+            }
+            NoiseColor::Velvet => self.next_velvet(velvet_period, rng),
+        }
+    }
+
+    /// Paul Kellet's refined pink noise filter.
+    fn next_pink(&mut self, white: f32) -> f32 {
+        let b = &mut self.pink;
+        b[0] = 0.99886 * b[0] + white * 0.055_517_9;
+        b[1] = 0.99332 * b[1] + white * 0.075_075_9;
+        b[2] = 0.96900 * b[2] + white * 0.153_852;
+        b[3] = 0.86650 * b[3] + white * 0.310_485_6;
+        b[4] = 0.55000 * b[4] + white * 0.532_952_2;
+        b[5] = -0.7616 * b[5] - white * 0.016_898;
+        let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+        b[6] = white * 0.115_926;
+
+        (pink * 0.11).clamp(-1.0, 1.0)
+    }
+
+    /// One impulse of random sign at a random position in each period.
+    fn next_velvet(&mut self, period: u32, rng: &mut StdRng) -> f32 {
+        if self.velvet_counter == 0 {
+            self.velvet_position = rng.gen_range(0..period);
+            self.velvet_sign = if rng.gen_bool(0.5) { 1.0 } else { -1.0 };
+        }
+
+        let value = if self.velvet_counter == self.velvet_position {
+            self.velvet_sign
+        } else {
+            0.0
+        };
+
+        self.velvet_counter = (self.velvet_counter + 1) % period;
+        value
+    }
+}
+
+/// Seeded stereo noise generator, does not allocate once built.
+#[derive(Clone)]
+pub struct NoiseGenerator {
+    color: NoiseColor,
+    rng: StdRng,
+    left: NoiseChannel,
+    right: NoiseChannel,
+    velvet_period: u32,
+    amplitude: f32,
+    shared_gain: f32,
+    independent_gain: f32,
+}
+
+impl NoiseGenerator {
+    pub fn new(options: &NoiseOptions, sample_rate: u32) -> Self {
+        let rng = match options.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+
+        // Keep the right channel power constant while mixing
+        let decorrelation = options.decorrelation.clamp(0.0, 1.0);
+        let norm = ((1.0 - decorrelation).powi(2) + decorrelation.powi(2)).sqrt();
+        let velvet_period = (sample_rate as f32 / options.density.max(1.0)).max(1.0) as u32;
+
+        Self {
+            color: options.color,
+            rng,
+            left: NoiseChannel::default(),
+            right: NoiseChannel::default(),
+            velvet_period,
+            amplitude: options.amplitude,
+            shared_gain: (1.0 - decorrelation) / norm,
+            independent_gain: decorrelation / norm,
+        }
+    }
+
+    pub fn next_frame(&mut self) -> [f32; 2] {
+        let left = self
+            .left
+            .next(self.color, self.velvet_period, &mut self.rng);
+
+        let right = if self.independent_gain > 0.0 {
+            let independent = self
+                .right
+                .next(self.color, self.velvet_period, &mut self.rng);
+            left * self.shared_gain + independent * self.independent_gain
+        } else {
+            left
+        };
+
+        [left * self.amplitude, right * self.amplitude]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustfft::{num_complex::Complex, FftPlanner};
+
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48_000;
+
+    fn generator(color: NoiseColor, seed: u64) -> NoiseGenerator {
+        let options = NoiseOptions {
+            color,
+            seed: Some(seed),
+            ..Default::default()
+        };
+        NoiseGenerator::new(&options, SAMPLE_RATE)
+    }
+
+    /// Slope of the left channel power spectrum from 250-500 Hz to 2-4 kHz, in dB per octave.
+    fn spectral_slope(color: NoiseColor) -> f64 {
+        const SIZE: usize = 4_096;
+        let mut generator = generator(color, 1);
+        let fft = FftPlanner::new().plan_fft_forward(SIZE);
+        let mut power = vec![0.0; SIZE / 2];
+        for _ in 0..64 {
+            let mut block: Vec<Complex<f64>> = (0..SIZE)
+                .map(|index| {
+                    let window =
+                        0.5 - 0.5 * (std::f64::consts::TAU * index as f64 / SIZE as f64).cos();
+                    Complex::new(generator.next_frame()[0] as f64 * window, 0.0)
+                })
+                .collect();
+            fft.process(&mut block);
+            for (power, bin) in power.iter_mut().zip(&block) {
+                *power += bin.norm_sqr();
+            }
+        }
+
+        let band = |low: f64| {
+            let bin = |frequency: f64| (frequency * SIZE as f64 / SAMPLE_RATE as f64) as usize;
+            let bins = &power[bin(low)..bin(2.0 * low)];
+            10.0 * (bins.iter().sum::<f64>() / bins.len() as f64).log10()
+        };
+        (band(2_000.0) - band(250.0)) / 3.0
+    }
+
+    #[test]
+    fn color_slopes() {
+        for (color, slope) in [
+            (NoiseColor::White, 0.0),
+            (NoiseColor::Pink, -3.0),
+            (NoiseColor::Brown, -6.0),
+            (NoiseColor::Blue, 3.0),
+            (NoiseColor::Violet, 6.0),
+            (NoiseColor::Velvet, 0.0),
+        ] {
+            let measured = spectral_slope(color);
+            assert!(
+                (measured - slope).abs() < 0.5,
+                "{color:?}: {measured} dB/octave"
+            );
+        }
+    }
+
+    #[test]
+    fn seeds_are_reproducible() {
+        let frames = |seed| {
+            let mut generator = generator(NoiseColor::Pink, seed);
+            (0..1_000)
+                .map(|_| generator.next_frame())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(frames(7), frames(7));
+        assert_ne!(frames(7), frames(8));
+        assert!(frames(7).iter().all(|[left, right]| left == right));
+    }
+}
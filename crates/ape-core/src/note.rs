@@ -0,0 +1,43 @@
+/// Frequency of a MIDI note, with A4 (69) at 440 Hz.
+pub fn note_to_freq(note: u8) -> f64 {
+    440.0 * ((note as f64 - 69.0) / 12.0).exp2()
+}
+
+/// Parse a note name like `C4`, `F#2` or `Bb-1` to a MIDI note, with C4 at 60.
+pub fn parse_note_name(name: &str) -> Option<u8> {
+    let mut chars = name.chars();
+    let base = match chars.next()?.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+
+    let rest = chars.as_str();
+    let (accidental, octave) = if let Some(octave) = rest.strip_prefix('#') {
+        (1, octave)
+    } else if let Some(octave) = rest.strip_prefix('b') {
+        (-1, octave)
+    } else {
+        (0, rest)
+    };
+
+    let octave: i32 = octave.parse().ok()?;
+    let note = (octave + 1) * 12 + base + accidental;
+    u8::try_from(note).ok().filter(|n| *n <= 127)
+}
+
+/// Parse a frequency in Hz, or a note name like `A4`.
+pub fn parse_frequency(s: &str) -> Result<f64, String> {
+    if let Some(note) = parse_note_name(s) {
+        return Ok(note_to_freq(note));
+    }
+    match s.parse::<f64>() {
+        Ok(frequency) if frequency > 0.0 => Ok(frequency),
+        _ => Err(format!("invalid frequency or note '{s}'")),
+    }
+}
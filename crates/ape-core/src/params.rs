@@ -0,0 +1,272 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use fundsp::hacker::{AudioUnit64, Tag};
+
+/// Parameter value shared between a control thread and the audio thread.
+#[derive(Debug, Clone, Default)]
+pub struct Param(Arc<AtomicU64>);
+
+impl Param {
+    pub fn new(value: f64) -> Self {
+        Self(Arc::new(AtomicU64::new(value.to_bits())))
+    }
+
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, value: f64) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Store a new value, returning the previous one in the same atomic operation.
+    pub fn swap(&self, value: f64) -> f64 {
+        f64::from_bits(self.0.swap(value.to_bits(), Ordering::Relaxed))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Smoothing {
+    /// Jump to the target value immediately.
+    None,
+    /// Linear ramp, lasting the given number of seconds.
+    Linear(f64),
+    /// One-pole ramp, with the given time constant in seconds.
+    Exponential(f64),
+    /// Linear ramp in octaves (portamento), lasting the given number of seconds.
+    /// Falls back to a linear ramp for non-positive values.
+    Glide(f64),
+}
+
+/// Per-sample ramp from the current value to a target value.
+#[derive(Debug, Clone)]
+pub struct Smoother {
+    smoothing: Smoothing,
+    sample_rate: f64,
+    current: f64,
+    target: f64,
+    step: f64,
+    remaining: u32,
+    log_domain: bool,
+}
+
+impl Smoother {
+    pub fn new(smoothing: Smoothing, value: f64, sample_rate: f64) -> Self {
+        Self {
+            smoothing,
+            sample_rate,
+            current: value,
+            target: value,
+            step: 0.0,
+            remaining: 0,
+            log_domain: false,
+        }
+    }
+
+    pub fn value(&self) -> f64 {
+        self.current
+    }
+
+    pub fn target(&self) -> f64 {
+        self.target
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.current != self.target
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate;
+        self.jump(self.target);
+    }
+
+    pub fn set_smoothing(&mut self, smoothing: Smoothing) {
+        self.smoothing = smoothing;
+    }
+
+    /// Move to a value without smoothing.
+    pub fn jump(&mut self, value: f64) {
+        self.current = value;
+        self.target = value;
+        self.remaining = 0;
+    }
+
+    /// Start a ramp towards a new target, if it changed.
+    pub fn set_target(&mut self, target: f64) {
+        if target == self.target {
+            return;
+        }
+
+        self.target = target;
+        match self.smoothing {
+            Smoothing::None => self.jump(target),
+            Smoothing::Linear(time) => self.start_ramp(time, false),
+            Smoothing::Glide(time) => {
+                let log_domain = self.current > 0.0 && target > 0.0;
+                self.start_ramp(time, log_domain)
+            }
+            Smoothing::Exponential(time) => {
+                if time <= 0.0 {
+                    self.jump(target);
+                } else {
+                    self.step = (-1.0 / (time * self.sample_rate)).exp();
+                }
+            }
+        }
+    }
+
+    fn start_ramp(&mut self, time: f64, log_domain: bool) {
+        let samples = (time * self.sample_rate) as u32;
+        if samples == 0 {
+            self.jump(self.target);
+            return;
+        }
+
+        self.log_domain = log_domain;
+        self.remaining = samples;
+        self.step = if log_domain {
+            (self.target.log2() - self.current.log2()) / samples as f64
+        } else {
+            (self.target - self.current) / samples as f64
+        };
+    }
+
+    /// Advance by one sample and return the new value.
+    pub fn tick(&mut self) -> f64 {
+        if !self.is_active() {
+            return self.current;
+        }
+
+        match self.smoothing {
+            Smoothing::Exponential(_) => {
+                self.current = self.target + (self.current - self.target) * self.step;
+                if (self.current - self.target).abs() <= 1e-6 * self.target.abs().max(1.0) {
+                    self.current = self.target;
+                }
+            }
+            _ => {
+                if self.remaining <= 1 {
+                    self.jump(self.target);
+                } else {
+                    self.remaining -= 1;
+                    self.current = if self.log_domain {
+                        (self.current.log2() + self.step).exp2()
+                    } else {
+                        self.current + self.step
+                    };
+                }
+            }
+        }
+
+        self.current
+    }
+}
+
+struct ParamBinding {
+    tag: Tag,
+    param: Param,
+    smoother: Smoother,
+}
+
+/// Shared parameters bound to tags of a running graph.
+pub struct GraphParams {
+    sample_rate: f64,
+    bindings: Vec<ParamBinding>,
+}
+
+impl GraphParams {
+    pub fn new(sample_rate: f64) -> Self {
+        Self {
+            sample_rate,
+            bindings: vec![],
+        }
+    }
+
+    pub fn bind(mut self, tag: Tag, param: Param, smoothing: Smoothing) -> Self {
+        let smoother = Smoother::new(smoothing, param.get(), self.sample_rate);
+        self.bindings.push(ParamBinding {
+            tag,
+            param,
+            smoother,
+        });
+        self
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate;
+        for binding in &mut self.bindings {
+            binding.smoother.set_sample_rate(sample_rate);
+        }
+    }
+
+    /// Read the shared values and update the graph tags, once per sample.
+    pub fn tick(&mut self, unit: &mut dyn AudioUnit64) {
+        for binding in &mut self.bindings {
+            binding.smoother.set_target(binding.param.get());
+            if binding.smoother.is_active() {
+                unit.set(binding.tag, binding.smoother.tick());
+            }
+        }
+    }
+
+    /// Apply the current values to a graph, without smoothing.
+    pub fn apply(&mut self, unit: &mut dyn AudioUnit64) {
+        for binding in &mut self.bindings {
+            binding.smoother.jump(binding.param.get());
+            unit.set(binding.tag, binding.smoother.value());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Values after each of `ticks` samples, at 1 kHz.
+    fn ramp(smoothing: Smoothing, from: f64, to: f64, ticks: usize) -> Vec<f64> {
+        let mut smoother = Smoother::new(smoothing, from, 1_000.0);
+        smoother.set_target(to);
+        (0..ticks).map(|_| smoother.tick()).collect()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn linear_ramp_lasts_its_time() {
+        let values = ramp(Smoothing::Linear(0.01), 0.0, 1.0, 12);
+        assert_close(values[4], 0.5);
+        assert!(values[8] < 1.0);
+        assert_eq!(values[9..], [1.0; 3]);
+    }
+
+    #[test]
+    fn glide_is_linear_in_octaves() {
+        let values = ramp(Smoothing::Glide(0.01), 220.0, 880.0, 10);
+        assert_close(values[4], 440.0);
+        assert_eq!(values[9], 880.0);
+
+        // Non-positive values glide linearly
+        let values = ramp(Smoothing::Glide(0.01), 0.0, 1.0, 10);
+        assert_close(values[4], 0.5);
+    }
+
+    #[test]
+    fn exponential_ramp_converges() {
+        let values = ramp(Smoothing::Exponential(0.01), 0.0, 1.0, 200);
+        assert_close(values[9], 1.0 - (-1.0_f64).exp());
+        assert!(values.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(values[199], 1.0);
+    }
+
+    #[test]
+    fn jumps_without_smoothing() {
+        assert_eq!(ramp(Smoothing::None, 0.0, 1.0, 1), [1.0]);
+        assert_eq!(ramp(Smoothing::Linear(0.0), 0.0, 1.0, 1), [1.0]);
+        assert_eq!(ramp(Smoothing::Exponential(0.0), 0.0, 1.0, 1), [1.0]);
+    }
+}
@@ -0,0 +1,209 @@
+use std::path::Path;
+
+use color_eyre::eyre::{self, eyre, WrapErr};
+
+/// Stereo frames decoded from an audio file.
+#[derive(Debug, Clone)]
+pub struct AudioFile {
+    pub frames: Vec<[f32; 2]>,
+    pub sample_rate: u32,
+}
+
+impl AudioFile {
+    /// Decode a .wav file, mono files playing on both channels and only the first two channels
+    /// of other files being kept.
+    pub fn from_wav(path: &Path) -> eyre::Result<Self> {
+        let mut reader = hound::WavReader::open(path)
+            .wrap_err_with(|| format!("could not read {}", path.display()))?;
+        let spec = reader.spec();
+        let channels = spec.channels as usize;
+
+        let samples: Vec<f32> = match spec.sample_format {
+            hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+            hound::SampleFormat::Int => {
+                let scale = 1.0 / (1_i64 << (spec.bits_per_sample - 1)) as f32;
+                reader
+                    .samples::<i32>()
+                    .map(|sample| sample.map(|sample| sample as f32 * scale))
+                    .collect::<Result<_, _>>()?
+            }
+        };
+
+        let frames = samples
+            .chunks_exact(channels)
+            .map(|frame| [frame[0], frame[channels.min(2) - 1]])
+            .collect();
+        Ok(Self {
+            frames,
+            sample_rate: spec.sample_rate,
+        })
+    }
+
+    /// Duration, in seconds.
+    pub fn duration(&self) -> f64 {
+        self.frames.len() as f64 / self.sample_rate as f64
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PlayerOptions {
+    /// Start over at `start` once the end is reached.
+    pub looping: bool,
+    /// Position to start from, in seconds.
+    pub start: f64,
+    /// Linear gain.
+    pub gain: f32,
+}
+
+impl Default for PlayerOptions {
+    fn default() -> Self {
+        Self {
+            looping: false,
+            start: 0.0,
+            gain: 1.0,
+        }
+    }
+}
+
+/// Plays an audio file at another sample rate, with linear interpolation.
+pub struct FilePlayer {
+    file: AudioFile,
+    options: PlayerOptions,
+    /// Position in the file, in frames.
+    position: f64,
+    /// File frames played per output frame.
+    step: f64,
+}
+
+impl FilePlayer {
+    pub fn new(file: AudioFile, options: PlayerOptions, sample_rate: u32) -> eyre::Result<Self> {
+        let position = options.start * file.sample_rate as f64;
+        if !options.start.is_finite() || options.start < 0.0 || position >= file.frames.len() as f64
+        {
+            return Err(eyre!(
+                "the start is outside of the file, which lasts {:.3} s",
+                file.duration()
+            ));
+        }
+
+        Ok(Self {
+            step: file.sample_rate as f64 / sample_rate as f64,
+            file,
+            options,
+            position,
+        })
+    }
+
+    /// Frame where playback starts, and loops start.
+    fn loop_start(&self) -> f64 {
+        self.options.start * self.file.sample_rate as f64
+    }
+
+    /// Set once the end of the file was played, when not looping.
+    pub fn is_finished(&self) -> bool {
+        self.position >= self.file.frames.len() as f64
+    }
+
+    /// Output frames left to play, when not looping.
+    pub fn remaining_frames(&self) -> usize {
+        let left = (self.file.frames.len() as f64 - self.position).max(0.0);
+        (left / self.step).ceil() as usize
+    }
+
+    pub fn next_frame(&mut self) -> [f32; 2] {
+        let frames = &self.file.frames;
+        if self.is_finished() {
+            return [0.0; 2];
+        }
+
+        let index = self.position as usize;
+        let fraction = (self.position - index as f64) as f32;
+        let current = frames[index];
+        // Interpolates towards the start of the loop, or silence, at the end of the file
+        let next = match frames.get(index + 1) {
+            Some(frame) => *frame,
+            None if self.options.looping => frames[self.loop_start() as usize],
+            None => [0.0; 2],
+        };
+
+        self.position += self.step;
+        if self.options.looping && self.is_finished() {
+            let loop_start = self.loop_start();
+            let loop_length = frames.len() as f64 - loop_start;
+            self.position = loop_start + (self.position - frames.len() as f64) % loop_length;
+        }
+
+        let gain = self.options.gain;
+        [
+            (current[0] + (next[0] - current[0]) * fraction) * gain,
+            (current[1] + (next[1] - current[1]) * fraction) * gain,
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp(len: usize, sample_rate: u32) -> AudioFile {
+        AudioFile {
+            frames: (0..len)
+                .map(|index| [index as f32, -(index as f32)])
+                .collect(),
+            sample_rate,
+        }
+    }
+
+    fn play(player: &mut FilePlayer, frames: usize) -> Vec<f32> {
+        (0..frames).map(|_| player.next_frame()[0]).collect()
+    }
+
+    #[test]
+    fn resampling_interpolates() {
+        let mut player =
+            FilePlayer::new(ramp(4, 24_000), PlayerOptions::default(), 48_000).unwrap();
+        assert_eq!(player.remaining_frames(), 8);
+        assert_eq!(
+            play(&mut player, 9),
+            [0.0, 0.5, 1.0, 1.5, 2.0, 2.5, 3.0, 1.5, 0.0]
+        );
+        assert!(player.is_finished());
+
+        let mut player =
+            FilePlayer::new(ramp(8, 48_000), PlayerOptions::default(), 24_000).unwrap();
+        assert_eq!(player.remaining_frames(), 4);
+        assert_eq!(play(&mut player, 4), [0.0, 2.0, 4.0, 6.0]);
+        assert_eq!(player.next_frame(), [0.0; 2]);
+    }
+
+    #[test]
+    fn looping_restarts_at_the_start() {
+        let options = PlayerOptions {
+            looping: true,
+            start: 1.0,
+            gain: 0.5,
+        };
+        let mut player = FilePlayer::new(ramp(4, 1), options, 1).unwrap();
+        assert_eq!(play(&mut player, 7), [0.5, 1.0, 1.5, 0.5, 1.0, 1.5, 0.5]);
+        assert!(!player.is_finished());
+
+        // The last frame interpolates towards the loop start
+        let options = PlayerOptions {
+            looping: true,
+            ..Default::default()
+        };
+        let mut player = FilePlayer::new(ramp(2, 2), options, 4).unwrap();
+        assert_eq!(play(&mut player, 6), [0.0, 0.5, 1.0, 0.5, 0.0, 0.5]);
+    }
+
+    #[test]
+    fn invalid_starts_are_rejected() {
+        for start in [-1.0, 4.0, f64::NAN, f64::INFINITY] {
+            let options = PlayerOptions {
+                start,
+                ..Default::default()
+            };
+            assert!(FilePlayer::new(ramp(4, 1), options, 1).is_err(), "{start}");
+        }
+    }
+}
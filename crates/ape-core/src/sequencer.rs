@@ -0,0 +1,683 @@
+use std::{path::Path, str::FromStr};
+
+use color_eyre::{eyre, eyre::eyre};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    note::parse_note_name,
+    transport::{TimeSignature, Transport},
+    voice::{Adsr, VoiceEngine, Waveform},
+};
+
+const POLYPHONY: usize = 8;
+/// Most repeats of a note inside a step.
+const MAX_RATCHETS: u32 = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub note: u8,
+    /// From 0 to 1.
+    pub velocity: f64,
+    /// Note length, in steps.
+    pub gate: f64,
+    /// Following steps the note is tied over, each lengthening it by a step.
+    pub ties: u32,
+    /// From 0 to 1.
+    pub probability: f64,
+    pub ratchets: u32,
+}
+
+impl FromStr for Step {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let name = parts.next().unwrap_or_default();
+        let note = parse_note_name(name).ok_or_else(|| format!("invalid note '{name}'"))?;
+        let mut step = Step {
+            note,
+            velocity: 100.0 / 127.0,
+            gate: 0.5,
+            ties: 0,
+            probability: 1.0,
+            ratchets: 1,
+        };
+
+        for modifier in parts {
+            let mut chars = modifier.chars();
+            let kind = chars.next();
+            let value = chars
+                .as_str()
+                .parse::<f64>()
+                .ok()
+                .filter(|value| value.is_finite())
+                .ok_or_else(|| format!("invalid step modifier '{modifier}'"));
+
+            match kind {
+                Some('v') => step.velocity = (value? / 127.0).clamp(0.0, 1.0),
+                Some('g') => step.gate = (value? / 100.0).max(0.0),
+                Some('p') => step.probability = (value? / 100.0).clamp(0.0, 1.0),
+                Some('r') => {
+                    let ratchets = value?;
+                    if ratchets > MAX_RATCHETS as f64 {
+                        return Err(format!("more than {MAX_RATCHETS} ratchets in '{modifier}'"));
+                    }
+                    step.ratchets = (ratchets as u32).max(1);
+                }
+                _ => return Err(format!("unknown step modifier '{modifier}'")),
+            }
+        }
+
+        Ok(step)
+    }
+}
+
+/// Note events, an off only releasing the on of the same id.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoteEvent {
+    On(u64, u8, f64),
+    Off(u64),
+}
+
+impl Step {
+    /// Note length including the tied steps, in steps.
+    pub fn length(&self) -> f64 {
+        self.gate + self.ties as f64
+    }
+
+    /// Timed events of the step starting at `start` and lasting `step_length`, with its ratchets
+    /// and gate, or none when the probability skips it. Ratchets take the ids from `first_id`.
+    pub fn events(
+        &self,
+        start: f64,
+        step_length: f64,
+        first_id: u64,
+        rng: &mut StdRng,
+    ) -> impl Iterator<Item = (f64, NoteEvent)> + '_ {
+        let repeats = if rng.gen_bool(self.probability) {
+            self.ratchets
+        } else {
+            0
+        };
+        let repeat_length = step_length / self.ratchets as f64;
+
+        (0..repeats).flat_map(move |repeat| {
+            let on = start + repeat as f64 * repeat_length;
+            // The last repeat holds on for the gate beyond the step
+            let mut length = repeat_length * self.length().min(1.0);
+            if repeat + 1 == self.ratchets {
+                length += (self.length() - 1.0).max(0.0) * step_length;
+            }
+
+            let id = first_id + repeat as u64;
+            [
+                (on, NoteEvent::On(id, self.note, self.velocity)),
+                (on + length, NoteEvent::Off(id)),
+            ]
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    pub name: String,
+    pub steps: Vec<Option<Step>>,
+    /// Step length, in quarter notes.
+    pub step_length: f64,
+}
+
+impl Pattern {
+    /// Pattern length, in quarter notes.
+    pub fn length(&self) -> f64 {
+        self.steps.len() as f64 * self.step_length
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Track {
+    pub name: String,
+    pub waveform: Waveform,
+    pub adsr: Adsr,
+    pub volume: f64,
+    pub patterns: Vec<Pattern>,
+    /// Pattern indices, in play order.
+    pub chain: Vec<usize>,
+    /// Inclusive chain indices to repeat once the chain ends.
+    pub loop_range: Option<(usize, usize)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Song {
+    pub tempo: f64,
+    pub time_signature: TimeSignature,
+    pub swing: f64,
+    pub seed: Option<u64>,
+    pub tracks: Vec<Track>,
+}
+
+impl Default for Song {
+    fn default() -> Self {
+        Self {
+            tempo: 120.0,
+            time_signature: TimeSignature::default(),
+            swing: 0.0,
+            seed: None,
+            tracks: vec![],
+        }
+    }
+}
+
+impl Song {
+    pub fn from_file(path: &Path) -> eyre::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        contents.parse()
+    }
+
+    /// Check that the song can be played: patterns have steps, chains and loop ranges point to
+    /// existing patterns.
+    pub fn validate(&self) -> eyre::Result<()> {
+        if !(self.tempo > 0.0 && self.tempo.is_finite()) {
+            return Err(eyre!("the tempo must be positive and finite"));
+        }
+
+        for track in &self.tracks {
+            for pattern in &track.patterns {
+                if pattern.steps.is_empty() {
+                    return Err(eyre!("track '{}' has an empty pattern", track.name));
+                }
+                if !(pattern.step_length > 0.0 && pattern.step_length.is_finite()) {
+                    return Err(eyre!(
+                        "pattern '{}' of track '{}' has an invalid step length",
+                        pattern.name,
+                        track.name
+                    ));
+                }
+            }
+
+            if track
+                .chain
+                .iter()
+                .any(|index| *index >= track.patterns.len())
+            {
+                return Err(eyre!("track '{}' chains a missing pattern", track.name));
+            }
+
+            if let Some((start, end)) = track.loop_range {
+                if start > end || end >= track.chain.len() {
+                    return Err(eyre!(
+                        "track '{}' has an invalid loop range {start}..{end}",
+                        track.name
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse a step division like `1/16`, to a length in quarter notes.
+pub fn parse_division(division: &str) -> Result<f64, String> {
+    let (numerator, denominator) = division
+        .split_once('/')
+        .ok_or_else(|| format!("invalid step division '{division}'"))?;
+    let numerator: f64 = numerator
+        .parse()
+        .map_err(|_| format!("invalid step division '{division}'"))?;
+    let denominator: f64 = denominator
+        .parse()
+        .map_err(|_| format!("invalid step division '{division}'"))?;
+
+    if numerator <= 0.0 || denominator <= 0.0 {
+        return Err(format!("invalid step division '{division}'"));
+    }
+
+    Ok(numerator * 4.0 / denominator)
+}
+
+fn parse_value<T: FromStr>(keyword: &str, value: Option<&str>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("missing value for '{keyword}'"))?;
+    value
+        .parse()
+        .map_err(|_| format!("invalid value '{value}' for '{keyword}'"))
+}
+
+fn parse_track<'a>(mut args: impl Iterator<Item = &'a str>) -> Result<Track, String> {
+    let name = args.next().ok_or("missing track name")?;
+    let mut track = Track {
+        name: name.into(),
+        waveform: Waveform::default(),
+        adsr: Adsr::default(),
+        volume: 0.5,
+        patterns: vec![],
+        chain: vec![],
+        loop_range: None,
+    };
+
+    for arg in args {
+        match arg.split_once('=') {
+            Some((key, value)) => match key {
+                "volume" => track.volume = parse_value(key, Some(value))?,
+                "attack" => track.adsr.attack = parse_value(key, Some(value))?,
+                "decay" => track.adsr.decay = parse_value(key, Some(value))?,
+                "sustain" => track.adsr.sustain = parse_value(key, Some(value))?,
+                "release" => track.adsr.release = parse_value(key, Some(value))?,
+                _ => return Err(format!("unknown track option '{key}'")),
+            },
+            None => track.waveform = arg.parse()?,
+        }
+    }
+
+    Ok(track)
+}
+
+/// Parse steps like `C2 . D#2:v80 -`, adding them to `steps`.
+pub fn parse_steps(steps: &mut Vec<Option<Step>>, line: &str) -> Result<(), String> {
+    for token in line.split_whitespace() {
+        match token {
+            "." => steps.push(None),
+            "-" => {
+                // Ties follow a note or another tie, not a rest
+                let len = steps.len();
+                let last = steps
+                    .iter_mut()
+                    .enumerate()
+                    .rev()
+                    .find_map(|(index, s)| s.as_mut().map(|s| (index, s)))
+                    .filter(|(index, step)| len - index - 1 == step.ties as usize)
+                    .map(|(_, step)| step)
+                    .ok_or("tie without a previous note")?;
+                last.ties += 1;
+                steps.push(None);
+            }
+            step => steps.push(Some(step.parse()?)),
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_line(song: &mut Song, line: &str) -> Result<(), String> {
+    let mut args = line.split_whitespace();
+    let keyword = match args.next() {
+        Some(keyword) => keyword,
+        None => return Ok(()),
+    };
+
+    match keyword {
+        "tempo" => song.tempo = parse_value(keyword, args.next())?,
+        "signature" => song.time_signature = parse_value(keyword, args.next())?,
+        "swing" => song.swing = parse_value(keyword, args.next())?,
+        "seed" => song.seed = Some(parse_value(keyword, args.next())?),
+        "track" => song.tracks.push(parse_track(args)?),
+        _ => {
+            let track = song
+                .tracks
+                .last_mut()
+                .ok_or_else(|| format!("'{keyword}' outside of a track"))?;
+
+            match keyword {
+                "pattern" => {
+                    let name = args.next().ok_or("missing pattern name")?;
+                    let step_length = parse_division(args.next().unwrap_or("1/16"))?;
+                    track.patterns.push(Pattern {
+                        name: name.into(),
+                        steps: vec![],
+                        step_length,
+                    });
+                }
+                "chain" => {
+                    for name in args {
+                        let index = track
+                            .patterns
+                            .iter()
+                            .position(|p| p.name == name)
+                            .ok_or_else(|| format!("unknown pattern '{name}'"))?;
+                        track.chain.push(index);
+                    }
+                }
+                "loop" => {
+                    let start = parse_value(keyword, args.next())?;
+                    let end = parse_value(keyword, args.next())?;
+                    track.loop_range = Some((start, end));
+                }
+                _ => {
+                    let pattern = track
+                        .patterns
+                        .last_mut()
+                        .ok_or_else(|| format!("steps outside of a pattern: '{line}'"))?;
+                    parse_steps(&mut pattern.steps, line)?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove a comment starting with '#', sharps being preceded by a note letter.
+fn strip_comment(line: &str) -> &str {
+    let mut previous = ' ';
+    for (index, c) in line.char_indices() {
+        if c == '#' && previous.is_whitespace() {
+            return &line[..index];
+        }
+        previous = c;
+    }
+
+    line
+}
+
+impl FromStr for Song {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut song = Song::default();
+        for (index, line) in s.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            parse_line(&mut song, line).map_err(|e| eyre!("line {}: {e}", index + 1))?;
+        }
+
+        // Play the patterns in declaration order when no chain is given
+        for track in &mut song.tracks {
+            if track.chain.is_empty() {
+                track.chain = (0..track.patterns.len()).collect();
+            }
+        }
+
+        song.validate()?;
+        Ok(song)
+    }
+}
+
+struct TrackPlayer {
+    track: Track,
+    voices: VoiceEngine,
+    chain_index: usize,
+    /// Set once the whole chain was played, the loop range repeating from then on.
+    looping: bool,
+    step_index: usize,
+    pattern_start: f64,
+    pending: Vec<(f64, NoteEvent)>,
+    next_id: u64,
+}
+
+impl TrackPlayer {
+    fn new(track: Track, sample_rate: f64) -> Self {
+        let voices = VoiceEngine::new(track.waveform, track.adsr, POLYPHONY, sample_rate);
+        Self {
+            track,
+            voices,
+            chain_index: 0,
+            looping: false,
+            step_index: 0,
+            pattern_start: 0.0,
+            pending: Vec::with_capacity(64),
+            next_id: 0,
+        }
+    }
+
+    fn pattern(&self) -> Option<&Pattern> {
+        let index = self.track.chain.get(self.chain_index)?;
+        self.track.patterns.get(*index)
+    }
+
+    fn next_pattern(&mut self) {
+        self.chain_index += 1;
+        let (start, end) = self
+            .track
+            .loop_range
+            .unwrap_or((0, self.track.chain.len().saturating_sub(1)));
+        if self.chain_index >= self.track.chain.len() || (self.looping && self.chain_index > end) {
+            self.chain_index = start;
+            self.looping = true;
+        }
+    }
+
+    /// Schedule the steps starting before `end`, in quarter notes.
+    fn schedule(&mut self, transport: &Transport, end: f64, rng: &mut StdRng) {
+        loop {
+            let (step, step_length, pattern_length) = match self.pattern() {
+                Some(pattern) => (
+                    pattern.steps.get(self.step_index).cloned(),
+                    pattern.step_length,
+                    pattern.length(),
+                ),
+                None => return,
+            };
+
+            let step = match step {
+                Some(step) => step,
+                None => {
+                    self.pattern_start += pattern_length;
+                    self.step_index = 0;
+                    self.next_pattern();
+                    continue;
+                }
+            };
+
+            let start =
+                self.pattern_start + transport.step_position(self.step_index as u64, step_length);
+            if start >= end {
+                return;
+            }
+
+            if let Some(step) = step {
+                self.pending
+                    .extend(step.events(start, step_length, self.next_id, rng));
+                self.next_id += step.ratchets as u64;
+            }
+
+            self.step_index += 1;
+        }
+    }
+
+    fn tick(&mut self, transport: &Transport, end: f64, rng: &mut StdRng) -> f64 {
+        self.schedule(transport, end, rng);
+
+        // Offs are sent before ons sharing the same position, to retrigger repeated notes
+        while let Some(index) = self
+            .pending
+            .iter()
+            .enumerate()
+            .filter(|(_, (position, _))| *position < end)
+            .min_by(|(_, (a, ea)), (_, (b, eb))| {
+                a.total_cmp(b).then_with(|| {
+                    matches!(ea, NoteEvent::On(..)).cmp(&matches!(eb, NoteEvent::On(..)))
+                })
+            })
+            .map(|(i, _)| i)
+        {
+            match self.pending.swap_remove(index).1 {
+                NoteEvent::On(id, note, velocity) => self.voices.tagged_note_on(note, velocity, id),
+                NoteEvent::Off(id) => self.voices.release(id),
+            }
+        }
+
+        self.voices.tick() * self.track.volume
+    }
+
+    fn seek(&mut self, beat: f64) {
+        self.pending.clear();
+        self.voices.all_notes_off();
+        self.chain_index = 0;
+        self.looping = false;
+        self.step_index = 0;
+        self.pattern_start = 0.0;
+
+        // Skip whole patterns before the position, the remaining steps being skipped on schedule
+        while let Some(pattern) = self.pattern() {
+            let length = pattern.length();
+            if self.pattern_start + length > beat {
+                break;
+            }
+            self.pattern_start += length;
+            self.next_pattern();
+        }
+
+        if let Some(pattern) = self.pattern() {
+            let steps = ((beat - self.pattern_start) / pattern.step_length).ceil();
+            self.step_index = steps.max(0.0) as usize;
+        }
+    }
+}
+
+/// Plays a song through voice engines, one track per engine.
+pub struct Sequencer {
+    transport: Transport,
+    tracks: Vec<TrackPlayer>,
+    rng: StdRng,
+}
+
+impl Sequencer {
+    pub fn new(song: Song, sample_rate: f64) -> eyre::Result<Self> {
+        song.validate()?;
+        let mut transport = Transport::new(song.tempo, sample_rate);
+        transport.set_time_signature(song.time_signature);
+        transport.set_swing(song.swing);
+
+        let rng = match song.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+
+        Ok(Self {
+            transport,
+            tracks: song
+                .tracks
+                .into_iter()
+                .map(|t| TrackPlayer::new(t, sample_rate))
+                .collect(),
+            rng,
+        })
+    }
+
+    pub fn transport(&self) -> &Transport {
+        &self.transport
+    }
+
+    pub fn transport_mut(&mut self) -> &mut Transport {
+        &mut self.transport
+    }
+
+    pub fn play(&mut self) {
+        self.transport.play();
+    }
+
+    pub fn stop(&mut self) {
+        self.transport.stop();
+        for track in &mut self.tracks {
+            track.voices.all_notes_off();
+        }
+    }
+
+    /// Jump to a position in quarter notes.
+    pub fn seek(&mut self, beat: f64) {
+        self.transport.seek(beat);
+        for track in &mut self.tracks {
+            track.seek(beat);
+        }
+    }
+
+    pub fn tick(&mut self) -> [f32; 2] {
+        let span = self.transport.tick();
+        let mut output = 0.0;
+        for track in &mut self.tracks {
+            let end = if span.is_empty() {
+                span.start
+            } else {
+                span.end
+            };
+            output += track.tick(&self.transport, end, &mut self.rng);
+        }
+
+        [output as f32, output as f32]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn step_modifiers() {
+        let step: Step = "C4:v64:g150:p25:r3".parse().unwrap();
+        assert_eq!(step.note, 60);
+        assert_eq!(step.velocity, 64.0 / 127.0);
+        assert_eq!(step.gate, 1.5);
+        assert_eq!(step.probability, 0.25);
+        assert_eq!(step.ratchets, 3);
+    }
+
+    #[test]
+    fn step_modifiers_are_clamped() {
+        let step: Step = "C4:v300:p150:r0".parse().unwrap();
+        assert_eq!(step.velocity, 1.0);
+        assert_eq!(step.probability, 1.0);
+        assert_eq!(step.ratchets, 1);
+
+        let step: Step = "C4:v-5:p-5".parse().unwrap();
+        assert_eq!(step.velocity, 0.0);
+        assert_eq!(step.probability, 0.0);
+    }
+
+    #[test]
+    fn invalid_step_modifiers() {
+        for step in [
+            "C4:pnan", "C4:vnan", "C4:rinf", "C4:ginf", "C4:r17", "C4:x50", "C4:é50", "C4:v", "C4:",
+        ] {
+            assert!(step.parse::<Step>().is_err(), "{step} was accepted");
+        }
+        assert_eq!("C4:r16".parse::<Step>().unwrap().ratchets, 16);
+    }
+
+    fn chain_order(song: &str, patterns: usize) -> Vec<usize> {
+        let song: Song = song.parse().unwrap();
+        let track = song.tracks.into_iter().next().unwrap();
+        let mut player = TrackPlayer::new(track, 44_100.0);
+        (0..patterns)
+            .map(|_| {
+                let index = player.track.chain[player.chain_index];
+                player.next_pattern();
+                index
+            })
+            .collect()
+    }
+
+    #[test]
+    fn loop_range_repeats_after_the_chain() {
+        let song = "track t\npattern a\nC4\npattern b\nD4\npattern c\nE4\n";
+        assert_eq!(chain_order(song, 7), [0, 1, 2, 0, 1, 2, 0]);
+        let looped = format!("{song}chain a b c\nloop 1 1\n");
+        assert_eq!(chain_order(&looped, 7), [0, 1, 2, 1, 1, 1, 1]);
+        let looped = format!("{song}chain a b c a\nloop 1 2\n");
+        assert_eq!(chain_order(&looped, 8), [0, 1, 2, 0, 1, 2, 1, 2]);
+    }
+
+    #[test]
+    fn unplayable_songs_are_rejected() {
+        let pattern = |steps, step_length| Pattern {
+            name: "a".into(),
+            steps,
+            step_length,
+        };
+        let song = |pattern| Song {
+            tracks: vec![Track {
+                name: "t".into(),
+                waveform: Waveform::default(),
+                adsr: Adsr::default(),
+                volume: 0.5,
+                patterns: vec![pattern],
+                chain: vec![0],
+                loop_range: None,
+            }],
+            ..Default::default()
+        };
+
+        let step = "C4".parse::<Step>().ok();
+        assert!(Sequencer::new(song(pattern(vec![step.clone()], 0.25)), 44_100.0).is_ok());
+        assert!(Sequencer::new(song(pattern(vec![], 0.25)), 44_100.0).is_err());
+        assert!(Sequencer::new(song(pattern(vec![step.clone()], 0.0)), 44_100.0).is_err());
+        assert!(Sequencer::new(song(pattern(vec![step], f64::NAN)), 44_100.0).is_err());
+    }
+}
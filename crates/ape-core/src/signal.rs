@@ -0,0 +1,342 @@
+use std::f64::consts::TAU;
+
+use color_eyre::eyre::{self, eyre};
+
+use crate::noise::{NoiseColor, NoiseGenerator, NoiseOptions};
+
+/// Rows and columns of the DTMF keypad, with their frequencies.
+const DTMF_KEYS: [&str; 4] = ["123A", "456B", "789C", "*0#D"];
+const DTMF_ROWS: [f64; 4] = [697.0, 770.0, 852.0, 941.0];
+const DTMF_COLUMNS: [f64; 4] = [1209.0, 1336.0, 1477.0, 1633.0];
+
+/// Bursts of the channel identification sequence, as start and end in seconds, and channels.
+const CHANNEL_BURSTS: [(f64, f64, [bool; 2]); 4] = [
+    (0.0, 0.5, [true, false]),
+    (1.5, 1.75, [false, true]),
+    (2.0, 2.25, [false, true]),
+    (3.0, 3.5, [true, true]),
+];
+const CHANNEL_CYCLE: f64 = 4.5;
+
+/// Fade in and out of tone bursts, in seconds, avoiding clicks.
+const RAMP: f64 = 0.005;
+
+/// Longest start of pink noise measured to scale it to its RMS level, in seconds.
+const NOISE_CALIBRATION: f64 = 10.0;
+
+/// Convert a level in dB to an amplitude.
+pub fn db_to_amplitude(db: f64) -> f64 {
+    10_f64.powf(db / 20.0)
+}
+
+/// Convert an amplitude to a level in dB.
+pub fn amplitude_to_db(amplitude: f64) -> f64 {
+    20.0 * amplitude.log10()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Signal {
+    Sine {
+        frequency: f64,
+    },
+    /// Sine sweep over the whole signal, exponential unless `linear` is set.
+    Sweep {
+        start: f64,
+        end: f64,
+        linear: bool,
+    },
+    /// A single sample at the level, repeated every `period` seconds when set.
+    Impulse {
+        period: Option<f64>,
+    },
+    Square {
+        frequency: f64,
+    },
+    Triangle {
+        frequency: f64,
+    },
+    /// Pink noise, the level being its RMS level.
+    PinkNoise {
+        seed: Option<u64>,
+    },
+    /// Tones of the DTMF keys in `digits`, lasting `tone` seconds, separated by `gap` seconds.
+    Dtmf {
+        digits: String,
+        tone: f64,
+        gap: f64,
+    },
+    /// Tone bursts on the left channel, then on the right channel, then on both, in a loop.
+    /// The signal lasts a whole number of 4.5 s cycles, its duration being rounded up.
+    Channels {
+        frequency: f64,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct SignalOptions {
+    pub signal: Signal,
+    /// Peak level in dBFS, or RMS level for pink noise.
+    pub level: f64,
+    /// Duration, in seconds.
+    pub duration: f64,
+}
+
+/// Instantaneous phase of a sine sweep `duration` seconds long, in radians, at `time` seconds.
+pub fn sweep_phase(start: f64, end: f64, duration: f64, linear: bool, time: f64) -> f64 {
+    if linear || start == end {
+        TAU * (start * time + (end - start) * time * time / (2.0 * duration))
+    } else {
+        let rate = (end / start).ln() / duration;
+        TAU * start * ((rate * time).exp() - 1.0) / rate
+    }
+}
+
+/// Amplitude of a burst from `start` to `end` seconds at `time`, with raised cosine ramps.
+fn burst(time: f64, start: f64, end: f64) -> f64 {
+    if time < start || time >= end {
+        return 0.0;
+    }
+    let edge = (time - start).min(end - time) / RAMP;
+    if edge >= 1.0 {
+        1.0
+    } else {
+        0.5 - 0.5 * (edge * std::f64::consts::PI).cos()
+    }
+}
+
+fn dtmf_frequencies(key: char) -> Option<(f64, f64)> {
+    let key = key.to_ascii_uppercase();
+    DTMF_KEYS.iter().enumerate().find_map(|(row, keys)| {
+        let column = keys.find(key)?;
+        Some((DTMF_ROWS[row], DTMF_COLUMNS[column]))
+    })
+}
+
+/// Renders a test signal of an exact duration and level, looping once it ends.
+pub struct SignalGenerator {
+    options: SignalOptions,
+    amplitude: f64,
+    sample_rate: f64,
+    frames: usize,
+    frame: usize,
+    /// Pink noise generators at the start of the signal, restarted when looping, and playing.
+    noise: Option<(NoiseGenerator, NoiseGenerator)>,
+    /// Gain bringing pink noise to its RMS level.
+    noise_gain: f64,
+}
+
+impl SignalGenerator {
+    pub fn new(options: SignalOptions, sample_rate: u32) -> eyre::Result<Self> {
+        // Looping in the middle of a cycle would restart the bursts early
+        let duration = match options.signal {
+            Signal::Channels { .. } => (options.duration / CHANNEL_CYCLE).ceil() * CHANNEL_CYCLE,
+            _ => options.duration,
+        };
+        if !duration.is_finite() {
+            return Err(eyre!("the signal must last a finite time"));
+        }
+        let frames = (duration * sample_rate as f64).round() as usize;
+        if frames == 0 {
+            return Err(eyre!("the signal must last at least one sample"));
+        }
+        if !options.level.is_finite() {
+            return Err(eyre!("the level must be finite"));
+        }
+        let nyquist = sample_rate as f64 / 2.0;
+
+        match &options.signal {
+            Signal::Sine { frequency }
+            | Signal::Square { frequency }
+            | Signal::Triangle { frequency }
+            | Signal::Channels { frequency } => {
+                if !(0.0 < *frequency && *frequency < nyquist) {
+                    return Err(eyre!("the frequency must be between 0 and {nyquist} Hz"));
+                }
+            }
+            Signal::Sweep { start, end, .. } => {
+                if [start, end].iter().any(|f| !(0.0 < **f && **f <= nyquist)) {
+                    return Err(eyre!(
+                        "sweep frequencies must be between 0 and {nyquist} Hz"
+                    ));
+                }
+            }
+            Signal::Impulse {
+                period: Some(period),
+            } if period.is_nan() || *period <= 0.0 => {
+                return Err(eyre!("the impulse period must be positive"));
+            }
+            Signal::Dtmf { digits, tone, gap } => {
+                if let Some(key) = digits.chars().find(|key| dtmf_frequencies(*key).is_none()) {
+                    return Err(eyre!("'{key}' is not a DTMF key (0-9, A-D, * and #)"));
+                }
+                if !(*tone > 0.0 && *gap >= 0.0) {
+                    return Err(eyre!("DTMF tones must last a positive time"));
+                }
+            }
+            _ => (),
+        }
+
+        let amplitude = db_to_amplitude(options.level);
+        let mut noise_gain = 0.0;
+        let noise = match options.signal {
+            Signal::PinkNoise { seed } => {
+                let noise_options = NoiseOptions {
+                    color: NoiseColor::Pink,
+                    seed,
+                    ..Default::default()
+                };
+                let generator = NoiseGenerator::new(&noise_options, sample_rate);
+
+                // Measured on the start of the signal, the level is exact for short signals
+                let calibration = frames.min((NOISE_CALIBRATION * sample_rate as f64) as usize);
+                let mut measured = generator.clone();
+                let power: f64 = (0..calibration)
+                    .map(|_| (measured.next_frame()[0] as f64).powi(2))
+                    .sum();
+                let rms = (power / calibration as f64).sqrt();
+                if rms > 0.0 {
+                    noise_gain = amplitude / rms;
+                }
+                Some((generator.clone(), generator))
+            }
+            _ => None,
+        };
+
+        Ok(Self {
+            options,
+            amplitude,
+            sample_rate: sample_rate as f64,
+            frames,
+            frame: 0,
+            noise,
+            noise_gain,
+        })
+    }
+
+    /// Length of the signal, in frames.
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    fn value_at(&self, frame: usize) -> [f64; 2] {
+        let time = frame as f64 / self.sample_rate;
+        // Phases are computed from the frame, so long signals don't drift
+        let cycle = |frequency: f64| (frame as f64 * frequency / self.sample_rate).fract();
+        let mono = |value: f64| [value; 2];
+
+        match &self.options.signal {
+            Signal::Sine { frequency } => mono((TAU * cycle(*frequency)).sin()),
+            Signal::Sweep { start, end, linear } => {
+                let duration = self.frames as f64 / self.sample_rate;
+                mono(sweep_phase(*start, *end, duration, *linear, time).sin())
+            }
+            Signal::Impulse { period } => {
+                let on = match period {
+                    Some(period) => {
+                        let period = (period * self.sample_rate).round().max(1.0) as usize;
+                        frame % period == 0
+                    }
+                    None => frame == 0,
+                };
+                mono(if on { 1.0 } else { 0.0 })
+            }
+            Signal::Square { frequency } => mono(if cycle(*frequency) < 0.5 { 1.0 } else { -1.0 }),
+            Signal::Triangle { frequency } => {
+                // Starts at zero and rises, like the sine
+                let phase = (cycle(*frequency) + 0.25).fract();
+                mono(1.0 - 4.0 * (phase - 0.5).abs())
+            }
+            // Rendered by `next_frame`, following the generator state
+            Signal::PinkNoise { .. } => [0.0; 2],
+            Signal::Dtmf { digits, tone, gap } => {
+                let slot = tone + gap;
+                let index = (time / slot) as usize;
+                let (low, high) = match digits.chars().nth(index).and_then(dtmf_frequencies) {
+                    Some(frequencies) => frequencies,
+                    None => return [0.0; 2],
+                };
+                let envelope = burst(time - index as f64 * slot, 0.0, *tone);
+                let value = (TAU * cycle(low)).sin() + (TAU * cycle(high)).sin();
+                mono(0.5 * envelope * value)
+            }
+            Signal::Channels { frequency } => {
+                let position = time % CHANNEL_CYCLE;
+                let value = (TAU * cycle(*frequency)).sin();
+                let mut output = [0.0; 2];
+                for (start, end, channels) in CHANNEL_BURSTS {
+                    let envelope = burst(position, start, end);
+                    for (output, on) in output.iter_mut().zip(channels) {
+                        if on {
+                            *output += envelope * value;
+                        }
+                    }
+                }
+                output
+            }
+        }
+    }
+
+    pub fn next_frame(&mut self) -> [f32; 2] {
+        if let Some((start, generator)) = &mut self.noise {
+            // Looping replays the same noise
+            if self.frame == 0 {
+                *generator = start.clone();
+            }
+            self.frame = (self.frame + 1) % self.frames;
+            let value = (generator.next_frame()[0] as f64 * self.noise_gain) as f32;
+            return [value; 2];
+        }
+
+        let [left, right] = self.value_at(self.frame);
+        self.frame = (self.frame + 1) % self.frames;
+        [
+            (left * self.amplitude) as f32,
+            (right * self.amplitude) as f32,
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generator(signal: Signal, duration: f64) -> eyre::Result<SignalGenerator> {
+        let options = SignalOptions {
+            signal,
+            level: -20.0,
+            duration,
+        };
+        SignalGenerator::new(options, 48_000)
+    }
+
+    #[test]
+    fn pink_noise_level_and_loop() {
+        let mut noise = generator(Signal::PinkNoise { seed: Some(1) }, 1.0).unwrap();
+        let frames: Vec<f32> = (0..96_000).map(|_| noise.next_frame()[0]).collect();
+        let (first, second) = frames.split_at(48_000);
+
+        let power: f64 = first.iter().map(|x| (*x as f64).powi(2)).sum();
+        let rms = (power / first.len() as f64).sqrt();
+        assert!((amplitude_to_db(rms) + 20.0).abs() < 1e-3);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn invalid_signals_are_rejected() {
+        let sine = |frequency| Signal::Sine { frequency };
+        assert!(generator(sine(1_000.0), f64::INFINITY).is_err());
+        assert!(generator(sine(1_000.0), f64::NAN).is_err());
+        assert!(generator(sine(f64::NAN), 1.0).is_err());
+        assert!(generator(sine(24_000.0), 1.0).is_err());
+        let sweep = Signal::Sweep {
+            start: 20.0,
+            end: f64::NAN,
+            linear: false,
+        };
+        assert!(generator(sweep, 1.0).is_err());
+        let impulse = Signal::Impulse {
+            period: Some(f64::NAN),
+        };
+        assert!(generator(impulse, 1.0).is_err());
+    }
+}
@@ -0,0 +1,298 @@
+use std::{
+    fmt::Display,
+    ops::Range,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use crate::params::Param;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeSignature {
+    pub numerator: u32,
+    pub denominator: u32,
+}
+
+impl TimeSignature {
+    pub fn new(numerator: u32, denominator: u32) -> Self {
+        Self {
+            numerator,
+            denominator,
+        }
+    }
+
+    /// Bar length, in quarter notes.
+    pub fn bar_length(&self) -> f64 {
+        self.numerator as f64 * 4.0 / self.denominator as f64
+    }
+
+    /// Zero-based bar index of a position in quarter notes.
+    pub fn bar(&self, position: f64) -> u64 {
+        (position / self.bar_length()) as u64
+    }
+
+    /// Position inside its bar, in beats of the time signature.
+    pub fn beat_in_bar(&self, position: f64) -> f64 {
+        let quarters = position - self.bar(position) as f64 * self.bar_length();
+        quarters * self.denominator as f64 / 4.0
+    }
+}
+
+impl Default for TimeSignature {
+    fn default() -> Self {
+        Self::new(4, 4)
+    }
+}
+
+impl Display for TimeSignature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.numerator, self.denominator)
+    }
+}
+
+impl FromStr for TimeSignature {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (numerator, denominator) = s
+            .split_once('/')
+            .ok_or_else(|| format!("invalid time signature '{s}'"))?;
+        let numerator: u32 = numerator
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid time signature numerator '{numerator}'"))?;
+        let denominator: u32 = denominator
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid time signature denominator '{denominator}'"))?;
+
+        if numerator == 0 || !denominator.is_power_of_two() {
+            return Err(format!("unsupported time signature '{s}'"));
+        }
+
+        Ok(Self::new(numerator, denominator))
+    }
+}
+
+/// Timing information reported by a host, at the start of a block.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HostTime {
+    pub playing: bool,
+    pub tempo: Option<f64>,
+    /// Position in quarter notes.
+    pub beat_position: Option<f64>,
+    pub time_signature: Option<TimeSignature>,
+}
+
+/// Transport state shared with control threads (GUI, CLI).
+#[derive(Debug, Clone)]
+pub struct TransportControl {
+    pub tempo: Param,
+    pub swing: Param,
+    numerator: Param,
+    denominator: Param,
+    playing: Arc<AtomicBool>,
+    seek: Param,
+    position: Param,
+}
+
+impl TransportControl {
+    pub fn new(tempo: f64) -> Self {
+        Self {
+            tempo: Param::new(tempo),
+            swing: Param::new(0.0),
+            numerator: Param::new(4.0),
+            denominator: Param::new(4.0),
+            playing: Arc::new(AtomicBool::new(false)),
+            seek: Param::new(f64::NAN),
+            position: Param::new(0.0),
+        }
+    }
+
+    pub fn time_signature(&self) -> TimeSignature {
+        TimeSignature::new(self.numerator.get() as u32, self.denominator.get() as u32)
+    }
+
+    pub fn set_time_signature(&self, time_signature: TimeSignature) {
+        self.numerator.set(time_signature.numerator as f64);
+        self.denominator.set(time_signature.denominator as f64);
+    }
+
+    pub fn play(&self) {
+        self.playing.store(true, Ordering::Relaxed);
+    }
+
+    pub fn stop(&self) {
+        self.playing.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing.load(Ordering::Relaxed)
+    }
+
+    /// Request a jump to a position in quarter notes.
+    pub fn seek(&self, beat: f64) {
+        self.seek.set(beat);
+    }
+
+    /// Last position published by the audio thread, in quarter notes.
+    pub fn position(&self) -> f64 {
+        self.position.get()
+    }
+}
+
+/// Musical clock, advanced sample by sample from the audio thread.
+#[derive(Debug, Clone)]
+pub struct Transport {
+    sample_rate: f64,
+    tempo: f64,
+    time_signature: TimeSignature,
+    swing: f64,
+    playing: bool,
+    sample_position: u64,
+    beat_position: f64,
+}
+
+impl Transport {
+    pub fn new(tempo: f64, sample_rate: f64) -> Self {
+        Self {
+            sample_rate,
+            tempo,
+            time_signature: TimeSignature::default(),
+            swing: 0.0,
+            playing: false,
+            sample_position: 0,
+            beat_position: 0.0,
+        }
+    }
+
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate;
+    }
+
+    pub fn tempo(&self) -> f64 {
+        self.tempo
+    }
+
+    pub fn set_tempo(&mut self, tempo: f64) {
+        self.tempo = tempo.max(1.0);
+    }
+
+    pub fn time_signature(&self) -> TimeSignature {
+        self.time_signature
+    }
+
+    pub fn set_time_signature(&mut self, time_signature: TimeSignature) {
+        self.time_signature = time_signature;
+    }
+
+    pub fn swing(&self) -> f64 {
+        self.swing
+    }
+
+    /// Swing amount, from 0 (straight) to 1 (odd steps delayed by half a step).
+    pub fn set_swing(&mut self, swing: f64) {
+        self.swing = swing.clamp(0.0, 1.0);
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn play(&mut self) {
+        self.playing = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.playing = false;
+    }
+
+    /// Jump to a position in quarter notes.
+    pub fn seek(&mut self, beat: f64) {
+        self.beat_position = beat.max(0.0);
+        self.sample_position = (self.beat_position * self.samples_per_beat()) as u64;
+    }
+
+    /// Samples elapsed while playing.
+    pub fn sample_position(&self) -> u64 {
+        self.sample_position
+    }
+
+    /// Position in quarter notes.
+    pub fn beat_position(&self) -> f64 {
+        self.beat_position
+    }
+
+    /// Zero-based bar index.
+    pub fn bar(&self) -> u64 {
+        self.time_signature.bar(self.beat_position)
+    }
+
+    /// Position inside the current bar, in beats of the time signature.
+    pub fn beat_in_bar(&self) -> f64 {
+        self.time_signature.beat_in_bar(self.beat_position)
+    }
+
+    pub fn samples_per_beat(&self) -> f64 {
+        self.sample_rate * 60.0 / self.tempo
+    }
+
+    /// Start of a step in quarter notes, odd steps being delayed by the swing.
+    pub fn step_position(&self, step: u64, step_length: f64) -> f64 {
+        let position = step as f64 * step_length;
+        if step % 2 == 1 {
+            position + self.swing * step_length * 0.5
+        } else {
+            position
+        }
+    }
+
+    /// Advance by one sample, returning the span of quarter notes it covers.
+    /// The span is empty when stopped.
+    pub fn tick(&mut self) -> Range<f64> {
+        let start = self.beat_position;
+        if self.playing {
+            self.sample_position += 1;
+            self.beat_position += 1.0 / self.samples_per_beat();
+        }
+
+        start..self.beat_position
+    }
+
+    /// Apply requests from a control thread and publish the position.
+    pub fn update(&mut self, control: &TransportControl) {
+        self.set_tempo(control.tempo.get());
+        self.set_swing(control.swing.get());
+        self.time_signature = control.time_signature();
+        self.playing = control.is_playing();
+
+        // Taken in one operation, so a seek requested meanwhile is not lost
+        let seek = control.seek.swap(f64::NAN);
+        if !seek.is_nan() {
+            self.seek(seek);
+        }
+
+        control.position.set(self.beat_position);
+    }
+
+    /// Follow the host timing information, at the start of a block.
+    pub fn follow(&mut self, host: &HostTime) {
+        self.playing = host.playing;
+        if let Some(tempo) = host.tempo {
+            self.set_tempo(tempo);
+        }
+        if let Some(time_signature) = host.time_signature {
+            self.time_signature = time_signature;
+        }
+        if let Some(beat) = host.beat_position {
+            self.seek(beat);
+        }
+    }
+}
@@ -0,0 +1,273 @@
+use std::str::FromStr;
+
+use fundsp::hacker::{dc, pulse, saw, sine, square, tag, triangle, AudioUnit64, Tag};
+
+use crate::note::note_to_freq;
+
+const FREQ_TAG: Tag = 0;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+    Sine,
+    #[default]
+    Saw,
+    Square,
+    Triangle,
+    /// Square wave with a variable width, 50% in voices.
+    Pulse,
+}
+
+impl Waveform {
+    fn build_unit(&self, sample_rate: f64) -> Box<dyn AudioUnit64> {
+        let freq = || tag(FREQ_TAG, 440.0);
+        let mut unit: Box<dyn AudioUnit64> = match self {
+            Self::Sine => Box::new(freq() >> sine()),
+            Self::Saw => Box::new(freq() >> saw()),
+            Self::Square => Box::new(freq() >> square()),
+            Self::Triangle => Box::new(freq() >> triangle()),
+            Self::Pulse => Box::new((freq() | dc(0.5)) >> pulse()),
+        };
+        unit.reset(Some(sample_rate));
+        unit
+    }
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "sine" => Ok(Self::Sine),
+            "saw" => Ok(Self::Saw),
+            "square" => Ok(Self::Square),
+            "triangle" => Ok(Self::Triangle),
+            "pulse" => Ok(Self::Pulse),
+            other => Err(format!("unknown waveform '{other}'")),
+        }
+    }
+}
+
+/// Envelope times in seconds, and sustain level.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Adsr {
+    pub attack: f64,
+    pub decay: f64,
+    pub sustain: f64,
+    pub release: f64,
+}
+
+impl Default for Adsr {
+    fn default() -> Self {
+        Self {
+            attack: 0.005,
+            decay: 0.1,
+            sustain: 0.7,
+            release: 0.1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Idle,
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+/// Linear ADSR envelope.
+#[derive(Debug, Clone)]
+pub(crate) struct Envelope {
+    adsr: Adsr,
+    sample_rate: f64,
+    stage: Stage,
+    level: f64,
+    release_step: f64,
+}
+
+impl Envelope {
+    pub(crate) fn new(adsr: Adsr, sample_rate: f64) -> Self {
+        Self {
+            adsr,
+            sample_rate,
+            stage: Stage::Idle,
+            level: 0.0,
+            release_step: 0.0,
+        }
+    }
+
+    fn step(&self, time: f64, distance: f64) -> f64 {
+        let samples = time * self.sample_rate;
+        if samples < 1.0 {
+            distance
+        } else {
+            distance / samples
+        }
+    }
+
+    pub(crate) fn gate_on(&mut self) {
+        self.stage = Stage::Attack;
+    }
+
+    pub(crate) fn gate_off(&mut self) {
+        if self.stage != Stage::Idle {
+            self.stage = Stage::Release;
+            self.release_step = self.step(self.adsr.release, self.level);
+        }
+    }
+
+    pub(crate) fn tick(&mut self) -> f64 {
+        match self.stage {
+            Stage::Idle => (),
+            Stage::Attack => {
+                self.level += self.step(self.adsr.attack, 1.0);
+                if self.level >= 1.0 {
+                    self.level = 1.0;
+                    self.stage = Stage::Decay;
+                }
+            }
+            Stage::Decay => {
+                self.level -= self.step(self.adsr.decay, 1.0 - self.adsr.sustain);
+                if self.level <= self.adsr.sustain {
+                    self.level = self.adsr.sustain;
+                    self.stage = Stage::Sustain;
+                }
+            }
+            Stage::Sustain => self.level = self.adsr.sustain,
+            Stage::Release => {
+                self.level -= self.release_step;
+                if self.level <= 0.0 {
+                    self.level = 0.0;
+                    self.stage = Stage::Idle;
+                }
+            }
+        }
+
+        self.level
+    }
+}
+
+struct Voice {
+    unit: Box<dyn AudioUnit64>,
+    envelope: Envelope,
+    note: u8,
+    velocity: f64,
+    gated: bool,
+    started_at: u64,
+    /// Id of a tagged note, only released by its id.
+    id: Option<u64>,
+}
+
+/// Polyphonic synth voices, triggered by note events.
+pub struct VoiceEngine {
+    voices: Vec<Voice>,
+    clock: u64,
+}
+
+impl VoiceEngine {
+    pub fn new(waveform: Waveform, adsr: Adsr, polyphony: usize, sample_rate: f64) -> Self {
+        let voices = (0..polyphony.max(1))
+            .map(|_| Voice {
+                unit: waveform.build_unit(sample_rate),
+                envelope: Envelope::new(adsr, sample_rate),
+                note: 0,
+                velocity: 0.0,
+                gated: false,
+                started_at: 0,
+                id: None,
+            })
+            .collect();
+
+        Self { voices, clock: 0 }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        for voice in &mut self.voices {
+            voice.unit.reset(Some(sample_rate));
+            voice.envelope.sample_rate = sample_rate;
+        }
+    }
+
+    /// Start a note, with a velocity from 0 to 1.
+    pub fn note_on(&mut self, note: u8, velocity: f64) {
+        self.start_note(note, velocity, None);
+    }
+
+    /// Start a note released by `release(id)`, for notes played again before their release.
+    pub fn tagged_note_on(&mut self, note: u8, velocity: f64, id: u64) {
+        self.start_note(note, velocity, Some(id));
+    }
+
+    fn start_note(&mut self, note: u8, velocity: f64, id: Option<u64>) {
+        // Retrigger the same note, then take an idle voice, then steal the oldest one
+        let index = self
+            .voices
+            .iter()
+            .position(|v| v.note == note && v.envelope.stage != Stage::Idle)
+            .or_else(|| {
+                self.voices
+                    .iter()
+                    .position(|v| v.envelope.stage == Stage::Idle)
+            })
+            .unwrap_or_else(|| {
+                self.voices
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, v)| v.started_at)
+                    .map(|(i, _)| i)
+                    .unwrap_or(0)
+            });
+
+        let voice = &mut self.voices[index];
+        voice.unit.set(FREQ_TAG, note_to_freq(note));
+        voice.envelope.gate_on();
+        voice.note = note;
+        voice.velocity = velocity.clamp(0.0, 1.0);
+        voice.gated = true;
+        voice.started_at = self.clock;
+        voice.id = id;
+    }
+
+    pub fn note_off(&mut self, note: u8) {
+        for voice in &mut self.voices {
+            if voice.gated && voice.note == note {
+                voice.gated = false;
+                voice.envelope.gate_off();
+            }
+        }
+    }
+
+    /// Release the note started with `id`, unless it was retriggered since.
+    pub fn release(&mut self, id: u64) {
+        for voice in &mut self.voices {
+            if voice.gated && voice.id == Some(id) {
+                voice.gated = false;
+                voice.envelope.gate_off();
+            }
+        }
+    }
+
+    pub fn all_notes_off(&mut self) {
+        for voice in &mut self.voices {
+            voice.gated = false;
+            voice.envelope.gate_off();
+        }
+    }
+
+    /// Mix the next mono sample of all active voices.
+    pub fn tick(&mut self) -> f64 {
+        self.clock += 1;
+        let mut output = 0.0;
+        for voice in &mut self.voices {
+            if voice.envelope.stage == Stage::Idle {
+                continue;
+            }
+
+            let level = voice.envelope.tick();
+            output += voice.unit.get_mono() * level * voice.velocity;
+        }
+
+        output
+    }
+}
@@ -1,17 +1,313 @@
 use std::sync::{
-    atomic::{AtomicBool, AtomicU16, Ordering},
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    mpsc::{self, Sender},
     Arc,
 };
 
-use ape_core::{color_eyre::eyre, dsp::build_dsp_chain_pitch, start_stream_thread, AudioOutput};
+use ape_bytebeats::{
+    BytebeatGenerator, BytebeatOptions, BytebeatSource, Dialect, FormulaInputs, INPUT_NAMES,
+};
+use ape_core::{
+    arp::{ArpEvent, ArpMode, ArpSettings, Arpeggiator},
+    color_eyre::eyre,
+    dsp::{build_dsp_chain_pitch, DspTag},
+    note::note_to_freq,
+    params::{GraphParams, Param, Smoothing},
+    start_stream_thread,
+    transport::{TimeSignature, Transport, TransportControl},
+    voice::{Adsr, VoiceEngine, Waveform},
+    AudioOutput,
+};
 
 use eframe::egui;
-use egui::Slider;
+use egui::{Key, Slider};
+
+/// Computer keys playing the notes of the on-screen keyboard.
+const KEYBOARD_KEYS: [Key; 13] = [
+    Key::A,
+    Key::W,
+    Key::S,
+    Key::E,
+    Key::D,
+    Key::F,
+    Key::T,
+    Key::G,
+    Key::Y,
+    Key::H,
+    Key::U,
+    Key::J,
+    Key::K,
+];
+const KEYBOARD_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+const KEYBOARD_FIRST_NOTE: u8 = 60;
+const ARP_RATES: [(f64, &str); 4] = [(1.0, "1/4"), (0.5, "1/8"), (0.25, "1/16"), (0.125, "1/32")];
+const TIME_SIGNATURES: [(u32, u32); 6] = [(2, 4), (3, 4), (4, 4), (5, 4), (6, 8), (7, 8)];
+
+/// Keys held on the keyboard, as a 128 bits mask read from the audio thread.
+#[derive(Clone, Default)]
+struct HeldKeys(Arc<[AtomicU64; 2]>);
+
+impl HeldKeys {
+    fn set(&self, note: u8, held: bool) {
+        let bit = 1 << (note % 64);
+        let word = &self.0[note as usize / 64];
+        if held {
+            word.fetch_or(bit, Ordering::Relaxed);
+        } else {
+            word.fetch_and(!bit, Ordering::Relaxed);
+        }
+    }
+
+    fn mask(&self) -> u128 {
+        let low = self.0[0].load(Ordering::Relaxed) as u128;
+        let high = self.0[1].load(Ordering::Relaxed) as u128;
+        low | high << 64
+    }
+}
+
+/// Arpeggiator settings shared with the audio thread.
+#[derive(Clone)]
+struct ArpControl {
+    enabled: Arc<AtomicBool>,
+    mode: Param,
+    rate: Param,
+    gate: Param,
+    octaves: Param,
+    latch: Arc<AtomicBool>,
+}
+
+impl Default for ArpControl {
+    fn default() -> Self {
+        let settings = ArpSettings::default();
+        Self {
+            enabled: Arc::new(AtomicBool::new(false)),
+            mode: Param::new(0.0),
+            rate: Param::new(settings.rate),
+            gate: Param::new(settings.gate),
+            octaves: Param::new(settings.octaves as f64),
+            latch: Arc::new(AtomicBool::new(settings.latch)),
+        }
+    }
+}
+
+impl ArpControl {
+    fn settings(&self) -> ArpSettings {
+        ArpSettings {
+            mode: ArpMode::ALL[self.mode.get() as usize],
+            octaves: self.octaves.get() as u32,
+            rate: self.rate.get(),
+            gate: self.gate.get(),
+            latch: self.latch.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Bytebeat formula edited in the UI, with its inputs shared with the audio thread.
+struct BytebeatControl {
+    enabled: Arc<AtomicBool>,
+    formula: String,
+    dialect: Dialect,
+    error: Option<String>,
+    inputs: FormulaInputs,
+    /// Sends compiled formulas to the audio thread.
+    generators: Sender<BytebeatGenerator>,
+    sample_rate: u32,
+}
+
+impl BytebeatControl {
+    fn load(&mut self) {
+        let options = BytebeatOptions {
+            dialect: self.dialect,
+            ..Default::default()
+        };
+        let source = BytebeatSource::Single(self.formula.clone());
+        match BytebeatGenerator::new(&source, &options, self.sample_rate) {
+            Ok(generator) => {
+                self.error = None;
+                self.generators.send(generator).ok();
+            }
+            Err(error) => self.error = Some(error.highlight()),
+        }
+    }
+}
 
-#[derive(Default)]
 struct MyApp {
     sound_enabled: Arc<AtomicBool>,
-    pitch: Arc<AtomicU16>,
+    pitch: Param,
+    transport: TransportControl,
+    keys: HeldKeys,
+    arp: ArpControl,
+    bytebeat: BytebeatControl,
+}
+
+impl MyApp {
+    fn bytebeat_ui(&mut self, ui: &mut egui::Ui) {
+        let bytebeat = &mut self.bytebeat;
+
+        ui.horizontal(|ui| {
+            let mut enabled = bytebeat.enabled.load(Ordering::Relaxed);
+            if ui.checkbox(&mut enabled, "Bytebeat").changed() {
+                bytebeat.enabled.store(enabled, Ordering::Relaxed);
+            }
+
+            for (dialect, name) in [(Dialect::C, "C"), (Dialect::Lua, "Lua")] {
+                if ui
+                    .selectable_label(bytebeat.dialect == dialect, name)
+                    .clicked()
+                {
+                    bytebeat.dialect = dialect;
+                }
+            }
+        });
+
+        ui.horizontal(|ui| {
+            let response = ui.text_edit_singleline(&mut bytebeat.formula);
+            let submitted = response.lost_focus() && ui.input().key_pressed(Key::Enter);
+            if ui.button("Load").clicked() || submitted {
+                bytebeat.load();
+            }
+        });
+
+        if let Some(error) = &bytebeat.error {
+            ui.colored_label(egui::Color32::RED, egui::RichText::new(error).monospace());
+        }
+
+        // Sliders `a` to `h`
+        for (slider, name) in bytebeat.inputs.sliders.iter().zip(INPUT_NAMES) {
+            let mut value = slider.get();
+            if ui
+                .add(Slider::new(&mut value, 0.0..=255.0).step_by(1.0).text(name))
+                .changed()
+            {
+                slider.set(value);
+            }
+        }
+    }
+
+    fn keyboard_ui(&self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            for (offset, key) in KEYBOARD_KEYS.iter().enumerate() {
+                let note = KEYBOARD_FIRST_NOTE + offset as u8;
+                let name = KEYBOARD_NAMES[offset % 12];
+                let pressed = ui.button(name).is_pointer_button_down_on();
+                let held = pressed || ui.input().key_down(*key);
+                self.keys.set(note, held);
+            }
+        });
+    }
+
+    fn arp_ui(&self, ui: &mut egui::Ui) {
+        let arp = &self.arp;
+
+        ui.horizontal(|ui| {
+            let mut enabled = arp.enabled.load(Ordering::Relaxed);
+            if ui.checkbox(&mut enabled, "Arpeggiator").changed() {
+                arp.enabled.store(enabled, Ordering::Relaxed);
+            }
+
+            let mut latch = arp.latch.load(Ordering::Relaxed);
+            if ui.checkbox(&mut latch, "Latch").changed() {
+                arp.latch.store(latch, Ordering::Relaxed);
+            }
+        });
+
+        ui.horizontal(|ui| {
+            let current = arp.mode.get() as usize;
+            for (index, mode) in ArpMode::ALL.iter().enumerate() {
+                if ui
+                    .selectable_label(index == current, mode.to_string())
+                    .clicked()
+                {
+                    arp.mode.set(index as f64);
+                }
+            }
+        });
+
+        ui.horizontal(|ui| {
+            let current = arp.rate.get();
+            for (rate, name) in ARP_RATES {
+                if ui.selectable_label(rate == current, name).clicked() {
+                    arp.rate.set(rate);
+                }
+            }
+        });
+
+        let mut gate = arp.gate.get();
+        if ui
+            .add(Slider::new(&mut gate, 0.05..=1.0).text("Gate"))
+            .changed()
+        {
+            arp.gate.set(gate);
+        }
+
+        let mut octaves = arp.octaves.get();
+        if ui
+            .add(
+                Slider::new(&mut octaves, 1.0..=4.0)
+                    .step_by(1.0)
+                    .text("Octaves"),
+            )
+            .changed()
+        {
+            arp.octaves.set(octaves);
+        }
+    }
+
+    fn transport_ui(&self, ui: &mut egui::Ui) {
+        let control = &self.transport;
+
+        ui.horizontal(|ui| {
+            let playing = control.is_playing();
+            if ui.button(if playing { "Stop" } else { "Play" }).clicked() {
+                if playing {
+                    control.stop();
+                } else {
+                    control.play();
+                }
+            }
+
+            if ui.button("Rewind").clicked() {
+                control.seek(0.0);
+            }
+
+            let time_signature = control.time_signature();
+            let position = control.position();
+            let bar = time_signature.bar(position);
+            let beat = time_signature.beat_in_bar(position).floor();
+            ui.monospace(format!("{}.{}", bar + 1, beat as u64 + 1));
+        });
+
+        ui.horizontal(|ui| {
+            let current = control.time_signature();
+            for (numerator, denominator) in TIME_SIGNATURES {
+                let time_signature = TimeSignature::new(numerator, denominator);
+                if ui
+                    .selectable_label(time_signature == current, time_signature.to_string())
+                    .clicked()
+                {
+                    control.set_time_signature(time_signature);
+                }
+            }
+        });
+
+        let mut tempo = control.tempo.get();
+        if ui
+            .add(Slider::new(&mut tempo, 40.0..=240.0).text("Tempo"))
+            .changed()
+        {
+            control.tempo.set(tempo);
+        }
+
+        let mut swing = control.swing.get();
+        if ui
+            .add(Slider::new(&mut swing, 0.0..=1.0).text("Swing"))
+            .changed()
+        {
+            control.swing.set(swing);
+        }
+    }
 }
 
 impl eframe::App for MyApp {
@@ -24,18 +320,39 @@ impl eframe::App for MyApp {
                 self.sound_enabled.store(enabled, Ordering::Relaxed);
             }
 
-            let mut pitch = self.pitch.load(Ordering::Relaxed) as f64;
+            let mut pitch = self.pitch.get();
             if ui
-                .add(
-                    Slider::new(&mut pitch, 220.0..=220.0 * 2.0)
-                        .step_by(1.0)
-                        .text("Pitch"),
-                )
+                .add(Slider::new(&mut pitch, 220.0..=220.0 * 2.0).text("Pitch"))
                 .changed()
             {
-                self.pitch.store(pitch as u16, Ordering::Relaxed);
+                self.pitch.set(pitch);
             }
+
+            ui.separator();
+            self.transport_ui(ui);
+
+            ui.separator();
+            self.arp_ui(ui);
+            self.keyboard_ui(ui);
+
+            ui.separator();
+            self.bytebeat_ui(ui);
         });
+
+        // Mouse position over the window, for the bytebeat `mx` and `my` inputs
+        let input = ctx.input();
+        if let Some(position) = input.pointer.hover_pos() {
+            let rect = input.screen_rect();
+            let x = (position.x - rect.left()) / rect.width();
+            let y = (position.y - rect.top()) / rect.height();
+            let mouse = &self.bytebeat.inputs.mouse;
+            mouse[0].set((x.clamp(0.0, 1.0) * 255.0).round() as f64);
+            mouse[1].set((y.clamp(0.0, 1.0) * 255.0).round() as f64);
+        }
+
+        if self.transport.is_playing() {
+            ctx.request_repaint();
+        }
     }
 }
 
@@ -43,28 +360,130 @@ fn main() -> eyre::Result<()> {
     let audio_output = AudioOutput::new_direct()?;
     let sound_running = Arc::new(AtomicBool::new(true));
     let sound_enabled = Arc::new(AtomicBool::new(false));
-    let pitch = Arc::new(AtomicU16::new(220));
+    let pitch = Param::new(220.0);
+    let transport_control = TransportControl::new(120.0);
+    let keys = HeldKeys::default();
+    let arp_control = ArpControl::default();
     let sample_rate = audio_output.sample_rate();
+    let bytebeat_enabled = Arc::new(AtomicBool::new(false));
+    let bytebeat_inputs = FormulaInputs::default();
+    let (generators, new_generators) = mpsc::channel();
+
+    let mut bytebeat = BytebeatControl {
+        enabled: bytebeat_enabled.clone(),
+        formula: "t*(t>>5|t>>8)".into(),
+        dialect: Dialect::C,
+        error: None,
+        inputs: bytebeat_inputs.clone(),
+        generators,
+        sample_rate,
+    };
+    bytebeat.load();
 
     let app = Box::new(MyApp {
         sound_enabled: sound_enabled.clone(),
         pitch: pitch.clone(),
+        transport: transport_control.clone(),
+        keys: keys.clone(),
+        arp: arp_control.clone(),
+        bytebeat,
     });
 
-    let mut last_pitch = pitch.load(Ordering::Relaxed) as f64;
-    let mut chain = build_dsp_chain_pitch(last_pitch, sample_rate);
+    let mut chain = build_dsp_chain_pitch(pitch.get(), sample_rate);
+    let mut params = GraphParams::new(sample_rate as f64).bind(
+        DspTag::Pitch as i64,
+        pitch,
+        Smoothing::Glide(0.05),
+    );
+    let mut transport = Transport::new(transport_control.tempo.get(), sample_rate as f64);
+    let mut voices = VoiceEngine::new(Waveform::Saw, Adsr::default(), 8, sample_rate as f64);
+    let mut arp = Arpeggiator::new(arp_control.settings());
+    let mut arp_enabled = false;
+    let mut last_keys = 0u128;
+    let mut generator: Option<BytebeatGenerator> = None;
+    // Last note started, played by the bytebeat
+    let mut bytebeat_note: Option<(u8, f64)> = None;
     let sample_fn = move || {
-        if sound_enabled.load(Ordering::Relaxed) {
-            let this_pitch = pitch.load(Ordering::Relaxed) as f64;
-            if last_pitch != this_pitch {
-                chain = build_dsp_chain_pitch(this_pitch, sample_rate);
-                last_pitch = this_pitch;
+        transport.update(&transport_control);
+        transport.tick();
+
+        // Switching the arpeggiator releases everything, held keys being played again
+        if arp_enabled != arp_control.enabled.load(Ordering::Relaxed) {
+            arp_enabled = !arp_enabled;
+            arp.clear();
+            voices.all_notes_off();
+            bytebeat_note = None;
+            last_keys = 0;
+        }
+        arp.set_settings(arp_control.settings());
+
+        let held_keys = keys.mask();
+        let changed = held_keys ^ last_keys;
+        if changed != 0 {
+            for note in 0..128u8 {
+                if changed & (1 << note) == 0 {
+                    continue;
+                }
+
+                let held = held_keys & (1 << note) != 0;
+                match (arp_enabled, held) {
+                    (true, true) => arp.note_on(note, 0.8),
+                    (true, false) => arp.note_off(note),
+                    (false, true) => {
+                        voices.note_on(note, 0.8);
+                        bytebeat_note = Some((note, 0.8));
+                    }
+                    (false, false) => {
+                        voices.note_off(note);
+                        bytebeat_note = bytebeat_note.filter(|(n, _)| *n != note);
+                    }
+                }
             }
+            last_keys = held_keys;
+        }
+
+        if arp_enabled {
+            for event in arp.tick(&transport).into_iter().flatten() {
+                match event {
+                    ArpEvent::NoteOn(note, velocity) => {
+                        voices.note_on(note, velocity);
+                        bytebeat_note = Some((note, velocity));
+                    }
+                    ArpEvent::NoteOff(note) => {
+                        voices.note_off(note);
+                        bytebeat_note = bytebeat_note.filter(|(n, _)| *n != note);
+                    }
+                }
+            }
+        }
+
+        if let Ok(new_generator) = new_generators.try_recv() {
+            generator = Some(new_generator);
+        }
+        match bytebeat_note {
+            Some((note, velocity)) => {
+                bytebeat_inputs.set_note(note_to_freq(note), velocity * 127.0)
+            }
+            None => bytebeat_inputs.velocity.set(0.0),
+        }
+
+        // The bytebeat replaces the keyboard voices when enabled
+        let keyboard = (voices.tick() * 0.3) as f32;
+        let mut keyboard = [keyboard, keyboard];
+        if let Some(generator) = &mut generator {
+            if bytebeat_enabled.load(Ordering::Relaxed) {
+                generator.set_inputs(bytebeat_inputs.values());
+                keyboard = generator.next_frame().map(|v| v * 0.3);
+            }
+        }
+
+        if sound_enabled.load(Ordering::Relaxed) {
+            params.tick(chain.as_mut());
 
             let v = chain.get_stereo();
-            vec![v.0 as f32, v.1 as f32]
+            [v.0 as f32 + keyboard[0], v.1 as f32 + keyboard[1]]
         } else {
-            vec![0.0, 0.0]
+            keyboard
         }
     };
 
@@ -1,6 +1,6 @@
 use std::sync::Arc;
 
-use crate::Parameters;
+use crate::{Parameter, Parameters};
 use baseview::{Size, WindowHandle, WindowOpenOptions, WindowScalePolicy};
 use egui::Context;
 use egui_baseview::EguiWindow;
@@ -84,6 +84,50 @@ fn draw_ui(ctx: &Context, params: &mut Arc<Parameters>) -> egui::Response {
                 {
                     params.modulation.set(val);
                 }
+
+                ui.separator();
+                for param in [
+                    Parameter::ArpMode,
+                    Parameter::ArpRate,
+                    Parameter::ArpGate,
+                    Parameter::ArpOctaves,
+                ] {
+                    let index = param as i32;
+                    let mut val = params.get_parameter(index);
+                    if ui
+                        .add(
+                            egui::Slider::new(&mut val, 0f32..=1f32)
+                                .show_value(false)
+                                .text(format!("{param}: {}", params.get_parameter_text(index))),
+                        )
+                        .changed()
+                    {
+                        params.set_parameter(index, val);
+                    }
+                }
+
+                let mut latch = params.arp_latch.get() > 0.5;
+                if ui.checkbox(&mut latch, "Arp latch").changed() {
+                    params.arp_latch.set(if latch { 1. } else { 0. });
+                }
+
+                ui.separator();
+                for param in
+                    std::iter::once(Parameter::BytebeatMix).chain(Parameter::BYTEBEAT_SLIDERS)
+                {
+                    let index = param as i32;
+                    let mut val = params.get_parameter(index);
+                    if ui
+                        .add(
+                            egui::Slider::new(&mut val, 0f32..=1f32)
+                                .show_value(false)
+                                .text(format!("{param}: {}", params.get_parameter_text(index))),
+                        )
+                        .changed()
+                    {
+                        params.set_parameter(index, val);
+                    }
+                }
             })
         })
         .response
@@ -1,21 +1,98 @@
 mod editor;
 
-use std::{fmt::Display, ops::RangeInclusive, sync::Arc, time::Duration};
-
+use std::{fmt::Display, ops::RangeInclusive, sync::Arc};
+
+use ape_bytebeats::{BytebeatGenerator, BytebeatOptions, BytebeatSource, InputValues, OutputMode};
+use ape_core::{
+    arp::{ArpEvent, ArpMode, ArpSettings, Arpeggiator},
+    params::{Smoother, Smoothing},
+    transport::{HostTime, TimeSignature, Transport},
+};
 use fundsp::hacker::*;
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
-use vst::prelude::*;
+use vst::{api::TimeInfoFlags, host::Host, prelude::*};
 use wmidi::{Note, Velocity};
 
+const ARP_RATES: [(f64, &str); 4] = [(1.0, "1/4"), (0.5, "1/8"), (0.25, "1/16"), (0.125, "1/32")];
+const ARP_MAX_OCTAVES: u32 = 4;
+
+/// Saw at the note frequency, crushed by the `a` slider and shaped by `b`.
+const BYTEBEAT_FORMULA: &str =
+    "((((t * freq * 256 // sr) & (255 - a) ~ t >> (4 + b // 32)) & 255) - 128) * vel // 127";
+
 pub struct Parameters {
     pub modulation: AtomicFloat,
+    pub arp_mode: AtomicFloat,
+    pub arp_rate: AtomicFloat,
+    pub arp_gate: AtomicFloat,
+    pub arp_octaves: AtomicFloat,
+    pub arp_latch: AtomicFloat,
+    pub bytebeat_mix: AtomicFloat,
+    pub bytebeat_sliders: [AtomicFloat; 8],
 }
 
 impl Default for Parameters {
     fn default() -> Self {
         Self {
             modulation: AtomicFloat::new(1.),
+            arp_mode: AtomicFloat::new(0.),
+            arp_rate: AtomicFloat::new(0.5),
+            arp_gate: AtomicFloat::new(0.5),
+            arp_octaves: AtomicFloat::new(0.),
+            arp_latch: AtomicFloat::new(0.),
+            bytebeat_mix: AtomicFloat::new(0.),
+            bytebeat_sliders: Default::default(),
+        }
+    }
+}
+
+/// Map a normalized parameter value to one of `count` choices.
+fn choice(value: f32, count: usize) -> usize {
+    Ord::min((value * count as f32) as usize, count - 1)
+}
+
+impl Parameters {
+    /// Arpeggiator mode, or `None` when the arpeggiator is off.
+    pub fn arp_mode(&self) -> Option<ArpMode> {
+        match choice(self.arp_mode.get(), ArpMode::ALL.len() + 1) {
+            0 => None,
+            index => Some(ArpMode::ALL[index - 1]),
+        }
+    }
+
+    pub fn arp_settings(&self) -> Option<ArpSettings> {
+        self.arp_mode().map(|mode| ArpSettings {
+            mode,
+            octaves: choice(self.arp_octaves.get(), ARP_MAX_OCTAVES as usize) as u32 + 1,
+            rate: ARP_RATES[choice(self.arp_rate.get(), ARP_RATES.len())].0,
+            gate: self.arp_gate.get().max(0.05) as f64,
+            latch: self.arp_latch.get() > 0.5,
+        })
+    }
+
+    /// Bytebeat sliders `a` to `h`, from 0 to 255.
+    pub fn bytebeat_sliders(&self) -> [f64; 8] {
+        std::array::from_fn(|index| (self.bytebeat_sliders[index].get() * 255.).round() as f64)
+    }
+
+    fn parameter(&self, param: Parameter) -> &AtomicFloat {
+        match param {
+            Parameter::Modulation => &self.modulation,
+            Parameter::ArpMode => &self.arp_mode,
+            Parameter::ArpRate => &self.arp_rate,
+            Parameter::ArpGate => &self.arp_gate,
+            Parameter::ArpOctaves => &self.arp_octaves,
+            Parameter::ArpLatch => &self.arp_latch,
+            Parameter::BytebeatMix => &self.bytebeat_mix,
+            Parameter::BytebeatA => &self.bytebeat_sliders[0],
+            Parameter::BytebeatB => &self.bytebeat_sliders[1],
+            Parameter::BytebeatC => &self.bytebeat_sliders[2],
+            Parameter::BytebeatD => &self.bytebeat_sliders[3],
+            Parameter::BytebeatE => &self.bytebeat_sliders[4],
+            Parameter::BytebeatF => &self.bytebeat_sliders[5],
+            Parameter::BytebeatG => &self.bytebeat_sliders[6],
+            Parameter::BytebeatH => &self.bytebeat_sliders[7],
         }
     }
 }
@@ -23,6 +100,34 @@ impl Default for Parameters {
 #[derive(FromPrimitive, Clone, Copy)]
 pub enum Parameter {
     Modulation = 0,
+    ArpMode = 1,
+    ArpRate = 2,
+    ArpGate = 3,
+    ArpOctaves = 4,
+    ArpLatch = 5,
+    BytebeatMix = 6,
+    BytebeatA = 7,
+    BytebeatB = 8,
+    BytebeatC = 9,
+    BytebeatD = 10,
+    BytebeatE = 11,
+    BytebeatF = 12,
+    BytebeatG = 13,
+    BytebeatH = 14,
+}
+
+impl Parameter {
+    pub const COUNT: i32 = 15;
+    pub const BYTEBEAT_SLIDERS: [Parameter; 8] = [
+        Self::BytebeatA,
+        Self::BytebeatB,
+        Self::BytebeatC,
+        Self::BytebeatD,
+        Self::BytebeatE,
+        Self::BytebeatF,
+        Self::BytebeatG,
+        Self::BytebeatH,
+    ];
 }
 
 impl Display for Parameter {
@@ -32,6 +137,20 @@ impl Display for Parameter {
             "{}",
             match self {
                 Parameter::Modulation => "modulation",
+                Parameter::ArpMode => "arp mode",
+                Parameter::ArpRate => "arp rate",
+                Parameter::ArpGate => "arp gate",
+                Parameter::ArpOctaves => "arp octaves",
+                Parameter::ArpLatch => "arp latch",
+                Parameter::BytebeatMix => "bytebeat mix",
+                Parameter::BytebeatA => "bytebeat a",
+                Parameter::BytebeatB => "bytebeat b",
+                Parameter::BytebeatC => "bytebeat c",
+                Parameter::BytebeatD => "bytebeat d",
+                Parameter::BytebeatE => "bytebeat e",
+                Parameter::BytebeatF => "bytebeat f",
+                Parameter::BytebeatG => "bytebeat g",
+                Parameter::BytebeatH => "bytebeat h",
             }
         )
     }
@@ -40,14 +159,40 @@ impl Display for Parameter {
 impl PluginParameters for Parameters {
     fn get_parameter(&self, index: i32) -> f32 {
         match FromPrimitive::from_i32(index) {
-            Some(Parameter::Modulation) => self.modulation.get(),
+            Some(param) => self.parameter(param).get(),
             _ => 0f32,
         }
     }
 
     fn set_parameter(&self, index: i32, value: f32) {
-        if let Some(Parameter::Modulation) = FromPrimitive::from_i32(index) {
-            self.modulation.set(value);
+        if let Some(param) = FromPrimitive::from_i32(index) {
+            self.parameter(param).set(value);
+        }
+    }
+
+    fn get_parameter_text(&self, index: i32) -> String {
+        let value = self.get_parameter(index);
+        match FromPrimitive::from_i32(index) {
+            Some(Parameter::ArpMode) => self
+                .arp_mode()
+                .map(|mode| mode.to_string())
+                .unwrap_or_else(|| "off".to_string()),
+            Some(Parameter::ArpRate) => ARP_RATES[choice(value, ARP_RATES.len())].1.to_string(),
+            Some(Parameter::ArpOctaves) => {
+                (choice(value, ARP_MAX_OCTAVES as usize) + 1).to_string()
+            }
+            Some(Parameter::ArpLatch) => if value > 0.5 { "on" } else { "off" }.to_string(),
+            Some(
+                Parameter::BytebeatA
+                | Parameter::BytebeatB
+                | Parameter::BytebeatC
+                | Parameter::BytebeatD
+                | Parameter::BytebeatE
+                | Parameter::BytebeatF
+                | Parameter::BytebeatG
+                | Parameter::BytebeatH,
+            ) => format!("{}", (value * 255.).round()),
+            _ => format!("{value:.2}"),
         }
     }
 
@@ -68,11 +213,18 @@ pub enum Tag {
 
 struct SynthTest {
     audio: Box<dyn AudioUnit64 + Send>,
+    host: HostCallback,
     parameters: Arc<Parameters>,
     note: Option<(Note, Velocity)>,
     enabled: bool,
     sample_rate: f32,
-    time: Duration,
+    frames: u64,
+    transport: Transport,
+    arp: Arpeggiator,
+    modulation: Smoother,
+    freq: Smoother,
+    bytebeat: Option<BytebeatGenerator>,
+    bytebeat_inputs: InputValues,
     editor: Option<editor::PluginEditor>,
 }
 
@@ -83,21 +235,110 @@ impl SynthTest {
     }
 
     #[inline(always)]
-    fn set_tag_with_param(&mut self, tag: Tag, param: Parameter, range: RangeInclusive<f64>) {
+    fn param_value(&self, param: Parameter, range: RangeInclusive<f64>) -> f64 {
         let value = self.parameters.get_parameter(param as i32) as f64;
-        let mapped_value = (value - range.start()) * (range.end() - range.start()) + range.start();
-        self.set_tag(tag, mapped_value);
+        (value - range.start()) * (range.end() - range.start()) + range.start()
+    }
+
+    /// Follow the host transport, or run freely when it does not report its time.
+    fn sync_transport(&mut self) {
+        let mask = TimeInfoFlags::TEMPO_VALID
+            | TimeInfoFlags::PPQ_POS_VALID
+            | TimeInfoFlags::TIME_SIG_VALID;
+        match self.host.get_time_info(mask.bits()) {
+            Some(info) => {
+                let flags = TimeInfoFlags::from_bits_truncate(info.flags);
+                let time_signature = TimeSignature::new(
+                    Ord::max(info.time_sig_numerator, 1) as u32,
+                    Ord::max(info.time_sig_denominator, 1) as u32,
+                );
+
+                self.transport.follow(&HostTime {
+                    playing: flags.contains(TimeInfoFlags::TRANSPORT_PLAYING),
+                    tempo: flags
+                        .contains(TimeInfoFlags::TEMPO_VALID)
+                        .then_some(info.tempo),
+                    beat_position: flags
+                        .contains(TimeInfoFlags::PPQ_POS_VALID)
+                        .then_some(info.ppq_pos),
+                    time_signature: flags
+                        .contains(TimeInfoFlags::TIME_SIG_VALID)
+                        .then_some(time_signature),
+                });
+            }
+            None => self.transport.play(),
+        }
+    }
+
+    fn start_note(&mut self, note: Note, velocity: Velocity) {
+        self.set_tag(Tag::NoteOn, self.time());
+        self.freq.set_target(note.to_freq_f64());
+        self.note = Some((note, velocity));
+        self.enabled = true;
+
+        self.bytebeat_inputs.freq = note.to_freq_f64();
+        self.bytebeat_inputs.velocity = u8::from(velocity) as f64;
+    }
+
+    fn stop_note(&mut self, note: Note) {
+        if let Some((current_note, ..)) = self.note {
+            if current_note == note {
+                self.note = None;
+                self.bytebeat_inputs.velocity = 0.;
+            }
+        }
+    }
+
+    fn apply_arp_event(&mut self, event: ArpEvent) {
+        match event {
+            ArpEvent::NoteOn(note, velocity) => self.start_note(
+                Note::from_u8_lossy(note),
+                Velocity::from_u8_lossy((velocity * 127.) as u8),
+            ),
+            ArpEvent::NoteOff(note) => self.stop_note(Note::from_u8_lossy(note)),
+        }
+    }
+
+    /// Follow the arpeggiator parameters, releasing its notes when it gets disabled.
+    fn sync_arp(&mut self) {
+        match self.parameters.arp_settings() {
+            Some(settings) => self.arp.set_settings(settings),
+            None => {
+                if let Some(event) = self.arp.clear() {
+                    self.apply_arp_event(event);
+                }
+            }
+        }
+    }
+
+    /// Build the bytebeat layer, its formula running at the output sample rate.
+    fn build_bytebeat(sample_rate: u32) -> Option<BytebeatGenerator> {
+        let options = BytebeatOptions {
+            mode: OutputMode::Signed(8),
+            rate: sample_rate,
+            ..Default::default()
+        };
+        let source = BytebeatSource::Single(BYTEBEAT_FORMULA.to_string());
+        BytebeatGenerator::new(&source, &options, sample_rate)
+            .map_err(|error| log::error!("invalid bytebeat formula: {error}"))
+            .ok()
+    }
+
+    #[inline(always)]
+    fn time(&self) -> f64 {
+        self.frames as f64 / self.sample_rate as f64
     }
 }
 
 impl Plugin for SynthTest {
     #[allow(clippy::precedence)] // Needed for chain
-    fn new(_host: HostCallback) -> Self {
+    fn new(host: HostCallback) -> Self {
         let params: Arc<Parameters> = Arc::new(Default::default());
-        let Parameters { modulation } = Parameters::default();
+        let Parameters { modulation, .. } = Parameters::default();
+        let initial_modulation = modulation.get() as f64;
 
         let freq = || tag(Tag::Freq as i64, 440.);
-        let modulation = || tag(Tag::Modulation as i64, modulation.get() as f64);
+        let modulation = || tag(Tag::Modulation as i64, initial_modulation);
         let offset = || tag(Tag::NoteOn as i64, 0.);
         let env = || offset() >> envelope2(|t, offset| downarc((t - offset) * 2.));
 
@@ -109,9 +350,16 @@ impl Plugin for SynthTest {
 
         Self {
             audio: Box::new(audio_graph) as Box<dyn AudioUnit64 + Send>,
+            host,
             parameters: params.clone(),
             note: None,
-            time: Duration::default(),
+            frames: 0,
+            transport: Transport::new(120., 44_100.),
+            arp: Arpeggiator::new(ArpSettings::default()),
+            modulation: Smoother::new(Smoothing::Exponential(0.02), initial_modulation, 44_100.),
+            freq: Smoother::new(Smoothing::Glide(0.03), 440., 44_100.),
+            bytebeat: Self::build_bytebeat(44_100),
+            bytebeat_inputs: InputValues::default(),
             sample_rate: 44_100f32,
             enabled: false,
             editor: Some(editor::PluginEditor {
@@ -150,7 +398,7 @@ impl Plugin for SynthTest {
             category: Category::Synth,
             inputs: 0,
             outputs: 2,
-            parameters: 1,
+            parameters: Parameter::COUNT,
             ..Default::default()
         }
     }
@@ -163,17 +411,21 @@ impl Plugin for SynthTest {
         for event in events.events() {
             if let vst::event::Event::Midi(midi) = event {
                 if let Ok(midi) = wmidi::MidiMessage::try_from(midi.data.as_slice()) {
+                    let arp_enabled = self.parameters.arp_mode().is_some();
                     match midi {
                         wmidi::MidiMessage::NoteOn(_channel, note, velocity) => {
-                            self.set_tag(Tag::NoteOn, self.time.as_secs_f64());
-                            self.note = Some((note, velocity));
-                            self.enabled = true;
+                            if arp_enabled {
+                                self.arp
+                                    .note_on(note.into(), u8::from(velocity) as f64 / 127.);
+                            } else {
+                                self.start_note(note, velocity);
+                            }
                         }
                         wmidi::MidiMessage::NoteOff(_channel, note, _velocity) => {
-                            if let Some((current_note, ..)) = self.note {
-                                if current_note == note {
-                                    self.note = None;
-                                }
+                            if arp_enabled {
+                                self.arp.note_off(note.into());
+                            } else {
+                                self.stop_note(note);
                             }
                         }
                         _ => (),
@@ -184,6 +436,9 @@ impl Plugin for SynthTest {
     }
 
     fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
+        self.sync_transport();
+        self.sync_arp();
+
         let (_, mut outputs) = buffer.split();
         if outputs.len() == 2 {
             let (left, right) = (outputs.get_mut(0), outputs.get_mut(1));
@@ -195,19 +450,54 @@ impl Plugin for SynthTest {
                 let mut left_buffer = [0f64; MAX_BUFFER_SIZE];
                 let mut right_buffer = [0f64; MAX_BUFFER_SIZE];
 
-                self.set_tag_with_param(Tag::Modulation, Parameter::Modulation, 0f64..=10f64);
+                let modulation = self.param_value(Parameter::Modulation, 0f64..=10f64);
+                self.modulation.set_target(modulation);
 
-                if let Some((note, ..)) = self.note {
-                    self.set_tag(Tag::Freq, note.to_freq_f64());
-                }
+                let bytebeat_mix = self.parameters.bytebeat_mix.get() as f64;
+                self.bytebeat_inputs.sliders = self.parameters.bytebeat_sliders();
+
+                for (left, right) in left_buffer
+                    .iter_mut()
+                    .zip(right_buffer.iter_mut())
+                    .take(left_chunk.len())
+                {
+                    self.transport.tick();
+                    for event in self.arp.tick(&self.transport).into_iter().flatten() {
+                        self.apply_arp_event(event);
+                    }
+
+                    if !self.enabled {
+                        continue;
+                    }
+
+                    // Update the smoothed tags sample by sample
+                    self.frames += 1;
+                    if self.modulation.is_active() {
+                        let value = self.modulation.tick();
+                        self.set_tag(Tag::Modulation, value);
+                    }
+                    if self.freq.is_active() {
+                        let value = self.freq.tick();
+                        self.set_tag(Tag::Freq, value);
+                    }
+
+                    let mut frame = [0f64; 2];
+                    self.audio.tick(&[], &mut frame);
+
+                    // Crossfade with the bytebeat layer
+                    if let Some(bytebeat) = &mut self.bytebeat {
+                        if bytebeat_mix > 0. {
+                            bytebeat.set_inputs(self.bytebeat_inputs);
+                            let [bytebeat_left, bytebeat_right] = bytebeat.next_frame();
+                            frame[0] = frame[0] * (1. - bytebeat_mix)
+                                + bytebeat_left as f64 * bytebeat_mix;
+                            frame[1] = frame[1] * (1. - bytebeat_mix)
+                                + bytebeat_right as f64 * bytebeat_mix;
+                        }
+                    }
 
-                if self.enabled {
-                    self.time += Duration::from_secs_f32(MAX_BUFFER_SIZE as f32 / self.sample_rate);
-                    self.audio.process(
-                        MAX_BUFFER_SIZE,
-                        &[],
-                        &mut [&mut left_buffer, &mut right_buffer],
-                    );
+                    *left = frame[0];
+                    *right = frame[1];
                 }
 
                 for (chunk, output) in left_chunk.iter_mut().zip(left_buffer.iter()) {
@@ -223,8 +513,12 @@ impl Plugin for SynthTest {
 
     fn set_sample_rate(&mut self, rate: f32) {
         self.sample_rate = rate;
-        self.time = Duration::default();
+        self.frames = 0;
+        self.transport.set_sample_rate(rate as f64);
+        self.modulation.set_sample_rate(rate as f64);
+        self.freq.set_sample_rate(rate as f64);
         self.audio.reset(Some(rate as f64));
+        self.bytebeat = Self::build_bytebeat(rate as u32);
     }
 
     fn can_do(&self, can_do: CanDo) -> Supported {